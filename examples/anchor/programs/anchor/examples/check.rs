@@ -4,7 +4,10 @@ use anchor_lang::{InstructionData, ToAccountMetas};
 use anyhow::Error;
 use clap::Parser;
 use fehler::throws;
-use solana_bpf_simulator::{SBPFInstructionExecutor, SBPFMessageExecutor, WorkingSlot, FEATURES};
+use solana_bpf_simulator::{
+    AccountLoadError, AccountSource, RpcAccountSource, SBPFInstructionExecutor,
+    SBPFMessageExecutor, FEATURES,
+};
 use solana_client::rpc_client::RpcClient;
 use solana_program_runtime::log_collector::LogCollector;
 use solana_sdk::{
@@ -60,13 +63,14 @@ fn run_ro(cli: Cli) {
         account: system_program::ID,
     }
     .to_account_metas(None);
-    let accounts: Vec<_> = accounts
+    let mut source = RpcAccountSource::new(&rpc);
+    let accounts = accounts
         .into_iter()
         .map(|meta| {
-            let key = meta.pubkey;
-            (meta, rpc.get_account(&key).unwrap_or_default())
+            let account = source.get_account(&meta.pubkey)?.unwrap_or_default();
+            Ok((meta, account))
         })
-        .collect();
+        .collect::<Result<Vec<_>, AccountLoadError>>()?;
 
     let mut exe = SBPFInstructionExecutor::new(40, (1, 120))?;
     exe.update_program(&program_id, &program_data, true)?;
@@ -132,14 +136,11 @@ fn run_full(cli: Cli) {
     }
     .into();
 
-    let mut loader = sbf.loader(|&key| {
-        if key == program_id {
-            return Some(program_data.clone());
-        }
-
-        let account = rpc.get_account(&key).unwrap_or_default();
-        return Some(account.into());
-    });
+    let working_slot = sbf.working_slot(slot);
+    // RPC failures are reported by load, only accounts that do not exist are loaded empty
+    let mut source = RpcAccountSource::new(&rpc);
+    source.insert(program_id, program_data);
+    let mut loader = sbf.loader(source);
 
     let ix_data = anchor_example::instruction::Initialize {}.data();
     let accounts = anchor_example::accounts::Initialize {
@@ -149,19 +150,15 @@ fn run_full(cli: Cli) {
     let ix = Instruction::new_with_bytes(program_id, &ix_data, accounts);
     let message = Message::new(&[ix], None);
     let message = SanitizedMessage::Legacy(LegacyMessage::new(message));
-    let loaded_transaction = loader.load_transaction_accounts(&message)?;
-    let loaded_programs = loader.replenish_program_cache(&WorkingSlot(slot), [&message])?;
+    let (loaded_transaction, loaded_programs) = loader.load(&working_slot, &message)?;
 
-    sbf.record_log();
     let res = sbf.process(slot, &message, loaded_transaction, &loaded_programs);
 
-    if let Err(e) = res {
-        println!(
-            "Invoke errored: {}:\nLogs: {:?}",
-            e,
-            sbf.logger().get_recorded_content()
-        );
-    } else {
-        println!("{:?}", sbf.logger().get_recorded_content());
+    match res {
+        Err(e) => println!("Invoke errored: {}", e),
+        Ok(record) => match record.result() {
+            Err(e) => println!("Invoke errored: {}:\nLogs: {:?}", e, record.logs),
+            Ok(()) => println!("{:?}", record.logs),
+        },
     }
 }
@@ -3,7 +3,9 @@ use std::{fs::File, io::Read};
 use anyhow::Error;
 use clap::Parser;
 use fehler::throws;
-use solana_bpf_simulator::{SBPFInstructionExecutor, SBPFMessageExecutor, WorkingSlot, FEATURES};
+use solana_bpf_simulator::{
+    AccountSource, RpcAccountSource, SBPFInstructionExecutor, SBPFMessageExecutor, FEATURES,
+};
 use solana_client::rpc_client::RpcClient;
 use solana_program::instruction::AccountMeta;
 use solana_program_runtime::log_collector::LogCollector;
@@ -53,7 +55,9 @@ fn run_ro(cli: Cli) {
 
     let accounts = vec![(
         AccountMeta::new_readonly(system_program::ID, false),
-        rpc.get_account(&system_program::ID).unwrap_or_default(),
+        RpcAccountSource::new(&rpc)
+            .get_account(&system_program::ID)?
+            .unwrap_or_default(),
     )];
 
     let mut exe = SBPFInstructionExecutor::new(
@@ -127,33 +131,26 @@ fn run_full(cli: Cli) {
     }
     .into();
 
-    let mut loader = sbf.loader(|&key| {
-        if key == program_id {
-            return Some(program_data.clone());
-        }
-
-        let account = rpc.get_account(&key).unwrap_or_default();
-        return Some(account.into());
-    });
+    let working_slot = sbf.working_slot(slot);
+    // RPC failures are reported by load, only accounts that do not exist are loaded empty
+    let mut source = RpcAccountSource::new(&rpc);
+    source.insert(program_id, program_data);
+    let mut loader = sbf.loader(source);
 
     let ix_data = vec![1, 2, 3, 4];
     let accounts = vec![AccountMeta::new_readonly(system_program::ID, false)];
     let ix = Instruction::new_with_bytes(program_id, &ix_data, accounts);
     let message = Message::new(&[ix], None);
     let message = SanitizedMessage::Legacy(LegacyMessage::new(message));
-    let loaded_transaction = loader.load_transaction_accounts(&message)?;
-    let loaded_programs = loader.replenish_program_cache(&WorkingSlot(slot), [&message])?;
+    let (loaded_transaction, loaded_programs) = loader.load(&working_slot, &message)?;
 
-    sbf.record_log();
     let res = sbf.process(slot, &message, loaded_transaction, &loaded_programs);
 
-    if let Err(e) = res {
-        println!(
-            "Invoke errored: {}:\nLogs: {:?}",
-            e,
-            sbf.logger().get_recorded_content()
-        );
-    } else {
-        println!("{:?}", sbf.logger().get_recorded_content());
+    match res {
+        Err(e) => println!("Invoke errored: {}", e),
+        Ok(record) => match record.result() {
+            Err(e) => println!("Invoke errored: {}:\nLogs: {:?}", e, record.logs),
+            Ok(()) => println!("{:?}", record.logs),
+        },
     }
 }
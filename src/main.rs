@@ -1,39 +1,66 @@
 use std::{
-    collections::{hash_map, HashMap},
-    fs::{File, OpenOptions},
+    collections::HashMap,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Error};
-use clap::{Parser, Subcommand};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 use fehler::{throw, throws};
-use solana_bpf_simulator::{MessageExecutor, WorkingSlot, FEATURES};
-use solana_client::rpc_client::RpcClient;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use solana_account_decoder::UiAccount;
+use solana_bpf_simulator::{
+    feature_set_from_accounts, system_account, upgradeable_program_accounts, AccountLoadError,
+    AccountSource, BuiltinSet, ExecutionRecord, MessageExecutor, RpcAccountSource,
+    RpcSimulateTransactionResult, FEATURES,
+};
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_response::{Response, RpcResponseContext},
+};
+use solana_program_runtime::loaded_programs::DELAY_VISIBILITY_SLOT_OFFSET;
 use solana_sdk::{
-    account::{Account, AccountSharedData, ReadableAccount},
+    account::{AccountSharedData, ReadableAccount, WritableAccount},
     account_utils::StateMut,
     bpf_loader,
     bpf_loader_upgradeable::{self, UpgradeableLoaderState},
-    clock::Clock,
+    clock::{Slot, UnixTimestamp},
+    feature_set::{FeatureSet, FEATURE_NAMES},
     instruction::{AccountMeta, Instruction},
-    message::{LegacyMessage, Message, SanitizedMessage},
+    message::{LegacyMessage, Message, SanitizedMessage, VersionedMessage},
     pubkey::Pubkey,
-    sysvar::clock,
+    rent::Rent,
+    system_program,
+    transaction::{TransactionError, VersionedTransaction},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::{
     filter::{EnvFilter, LevelFilter},
-    fmt,
+    fmt::{self, writer::BoxMakeWriter},
     prelude::*,
     Registry,
 };
 use url::Url;
 
+mod scenario;
+mod serve;
+
+use scenario::Scenario;
+use serve::Serve;
+
+const DEFAULT_SOLANA_RPC: &str = "https://api.mainnet-beta.solana.com";
+
 #[derive(Parser)]
 struct Cli {
-    #[arg(long, env, default_value = "https://api.mainnet-beta.solana.com")]
-    solana_rpc: Url,
+    /// Defaults to mainnet-beta, except for serve which only forwards to it when given
+    #[arg(long, env)]
+    solana_rpc: Option<Url>,
 
     #[command(subcommand)]
     command: Command,
@@ -41,17 +68,23 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Command {
-    Simulate(Simulate),
+    Simulate(Box<Simulate>),
     GetProgramData(GetProgramData),
+    Serve(Serve),
 }
 
 #[throws(Error)]
 fn main() {
     let cli = Cli::parse();
+    // The JSON output is the only thing printed to stdout, the logs go to stderr
+    let writer = match &cli.command {
+        Command::Simulate(c) if c.output == Output::Json => BoxMakeWriter::new(std::io::stderr),
+        _ => BoxMakeWriter::new(std::io::stdout),
+    };
     let subscriber = Registry::default()
         .with(
             fmt::layer()
-                .with_writer(std::io::stdout)
+                .with_writer(writer)
                 .with_filter(LevelFilter::INFO),
         )
         .with(EnvFilter::builder().try_from_env().unwrap_or_else(|_| {
@@ -62,76 +95,399 @@ fn main() {
 
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
-    let rpc = RpcClient::new(cli.solana_rpc.to_string());
+    let upstream = cli.solana_rpc.map(|url| RpcClient::new(url.to_string()));
+    let default_rpc;
+    let rpc = match &upstream {
+        Some(rpc) => rpc,
+        None => {
+            default_rpc = RpcClient::new(DEFAULT_SOLANA_RPC.to_string());
+            &default_rpc
+        }
+    };
 
     match cli.command {
-        Command::Simulate(c) => c.run(&rpc)?,
-        Command::GetProgramData(c) => c.run(&rpc)?,
+        Command::Simulate(c) => c.run(rpc)?,
+        Command::GetProgramData(c) => c.run(rpc)?,
+        Command::Serve(c) => c.run(upstream.as_ref())?,
     }
 }
 
+// The inputs that define their own accounts and fee payer
+const SERIALIZED_INPUTS: [&str; 4] = ["transaction", "transaction_file", "message", "scenario"];
+
 #[derive(Debug, Clone, Parser)]
+#[command(group(ArgGroup::new("input").required(true)))]
 struct Simulate {
     #[arg(long, default_value = "FAKEPRoGRAM1D111111111111111111111111111111")]
     program_id: Pubkey,
 
-    #[arg(long, default_value = "program.so")]
-    program: PathBuf,
-
+    /// Defaults to program.so with --instruction, otherwise the program is loaded from the cluster
     #[arg(long)]
-    instruction: String, // base58 string
+    program: Option<PathBuf>,
 
-    #[arg(long)]
+    #[arg(long, group = "input")]
+    instruction: Option<String>, // base58 string
+
+    /// A serialized VersionedTransaction, base58 or base64 encoded
+    #[arg(long, group = "input")]
+    transaction: Option<String>,
+
+    /// A file with a serialized VersionedTransaction, raw or base58/base64 encoded
+    #[arg(long, group = "input")]
+    transaction_file: Option<PathBuf>,
+
+    /// A serialized unsigned VersionedMessage, base58 or base64 encoded
+    #[arg(long, group = "input")]
+    message: Option<String>,
+
+    /// A TOML, JSON or YAML file with instructions, programs, accounts and expected results
+    #[arg(long, group = "input")]
+    scenario: Option<PathBuf>,
+
+    /// Only with --instruction, the other inputs carry their accounts
+    #[arg(long, conflicts_with_all = SERIALIZED_INPUTS)]
     account: Vec<Pubkey>,
 
+    /// Also marks these accounts as signers of --transaction and --message inputs
     #[arg(long)]
     signer_account: Vec<Pubkey>,
 
-    #[arg(long)]
+    /// Only with --instruction
+    #[arg(long, conflicts_with_all = SERIALIZED_INPUTS)]
     writable_account: Vec<Pubkey>,
+
+    /// Only with --instruction, the other inputs carry their fee payer
+    #[arg(long, conflicts_with_all = SERIALIZED_INPUTS)]
+    fee_payer: Option<Pubkey>,
+
+    /// Replace an account with a file, as <pubkey>=<path>. The file is a JSON dump of
+    /// `solana account --output json`, or the account data in base64 or raw bytes.
+    #[arg(long, value_parser = parse_key_value::<PathBuf>)]
+    account_override: Vec<(Pubkey, PathBuf)>,
+
+    /// Set the lamports of an account, as <pubkey>=<lamports>
+    #[arg(long, value_parser = parse_key_value::<u64>)]
+    lamports: Vec<(Pubkey, u64)>,
+
+    /// Set the owner of an account, as <pubkey>=<owner>
+    #[arg(long, value_parser = parse_key_value::<Pubkey>)]
+    owner: Vec<(Pubkey, Pubkey)>,
+
+    /// Simulate at this slot with synthetic sysvars instead of fetching them from the cluster
+    #[arg(long)]
+    slot: Option<Slot>,
+
+    /// Use the features activated on the cluster instead of the built-in list
+    #[arg(long)]
+    cluster_features: bool,
+
+    #[arg(long)]
+    enable_feature: Vec<Pubkey>,
+
+    #[arg(long)]
+    disable_feature: Vec<Pubkey>,
+
+    /// Do not register this builtin program, invoking it fails
+    #[arg(long)]
+    disable_builtin: Vec<Pubkey>,
+
+    /// Replace a builtin program with an ELF, e.g. its core BPF version, as <program id>=<path>
+    #[arg(long, value_parser = parse_key_value::<PathBuf>)]
+    substitute_builtin: Vec<(Pubkey, PathBuf)>,
+
+    /// Print a simulateTransaction compatible JSON response instead of logs
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Text,
+    Json,
 }
 
 impl Simulate {
     #[throws(Error)]
     fn run(&self, rpc: &RpcClient) {
-        let mut sbf = MessageExecutor::new(FEATURES).unwrap();
+        // Every account of the cluster goes through the source, so that RPC failures are reported
+        // instead of being taken for missing accounts
+        let mut source = RpcAccountSource::new(rpc);
+
+        let mut feature_set = if self.cluster_features {
+            let slot = match self.slot {
+                Some(slot) => slot,
+                None => rpc.get_slot()?,
+            };
+            let feature_ids: Vec<_> = FEATURE_NAMES.keys().copied().collect();
+            source.prefetch_accounts(&feature_ids);
+            let mut feature_accounts = HashMap::new();
+            for key in feature_ids {
+                if let Some(account) = source.get_account(&key)? {
+                    feature_accounts.insert(key, account);
+                }
+            }
+            feature_set_from_accounts(slot, |key| feature_accounts.get(key).cloned())
+        } else {
+            let mut feature_set = FeatureSet::default();
+            for feature_id in FEATURES {
+                feature_set.activate(feature_id, 0);
+            }
+            feature_set
+        };
+        for feature_id in &self.enable_feature {
+            feature_set.activate(feature_id, 0);
+        }
+        for feature_id in &self.disable_feature {
+            feature_set.deactivate(feature_id);
+        }
 
-        let clock = rpc.get_account(&clock::id())?;
-        let clock: Clock = bincode::deserialize(&clock.data())?;
+        let mut builtins = BuiltinSet::new();
+        for program_id in &self.disable_builtin {
+            builtins = builtins.disable(*program_id);
+        }
+        for (program_id, path) in &self.substitute_builtin {
+            builtins = builtins.substitute(*program_id, fs::read(path)?);
+        }
+        let mut sbf = MessageExecutor::new_with_builtins(feature_set, &builtins)?;
 
-        let slot = clock.slot;
-        sbf.sysvar_cache_mut().set_clock(clock);
+        match self.slot {
+            Some(slot) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                sbf.set_synthetic_sysvars(slot, now as UnixTimestamp);
+            }
+            None => sbf.fill_sysvar_cache(&mut source)?,
+        }
+        let slot = sbf.sysvar_cache().get_clock()?.slot;
 
-        let mut data = vec![];
-        File::open(&self.program)?.read_to_end(&mut data)?;
+        let scenario = self.scenario.as_deref().map(Scenario::load).transpose()?;
 
-        let program_data: AccountSharedData = Account {
-            lamports: 1,
-            data,
-            owner: bpf_loader::id(),
-            executable: true,
-            rent_epoch: 0,
+        let mut programs = vec![];
+        match (&self.program, &self.instruction) {
+            (Some(program), _) => programs.push((self.program_id, program.clone())),
+            (None, Some(_)) => programs.push((self.program_id, PathBuf::from("program.so"))),
+            (None, None) => {}
+        };
+        if let Some(scenario) = &scenario {
+            programs.extend(
+                scenario
+                    .programs
+                    .iter()
+                    .map(|(program_id, path)| (program_id.0, path.clone())),
+            );
         }
-        .into();
 
         let mut accounts: HashMap<Pubkey, AccountSharedData> = HashMap::new();
-        let mut loader = sbf.loader(|&key| {
-            if key == self.program_id {
-                return Some(program_data.clone());
+        for (program_id, program) in programs {
+            let mut data = vec![];
+            File::open(program)?.read_to_end(&mut data)?;
+
+            // Deployed in the previous slot so that it is visible in the simulated one
+            accounts.extend(upgradeable_program_accounts(
+                &program_id,
+                &data,
+                None,
+                slot.saturating_sub(DELAY_VISIBILITY_SLOT_OFFSET),
+                &sbf.rent(),
+            )?);
+        }
+        if let Some(scenario) = &scenario {
+            accounts.extend(scenario.accounts()?);
+        }
+        for (key, path) in &self.account_override {
+            accounts.insert(*key, read_account(path, &sbf.rent())?);
+        }
+        // Applied on top of the account on the cluster if not overridden, or a new system account
+        for (key, lamports) in &self.lamports {
+            if !accounts.contains_key(key) {
+                let account = source
+                    .get_account(key)?
+                    .unwrap_or_else(|| system_account(0));
+                accounts.insert(*key, account);
+            }
+            accounts.get_mut(key).unwrap().set_lamports(*lamports);
+        }
+        for (key, owner) in &self.owner {
+            if !accounts.contains_key(key) {
+                let account = source
+                    .get_account(key)?
+                    .unwrap_or_else(|| system_account(0));
+                accounts.insert(*key, account);
             }
+            accounts.get_mut(key).unwrap().set_owner(*owner);
+        }
+        for (key, account) in accounts {
+            source.insert(key, account);
+        }
 
-            match accounts.entry(key) {
-                hash_map::Entry::Occupied(e) => return Some(e.get().clone()),
-                hash_map::Entry::Vacant(e) => {
-                    let account = rpc.get_account(&key).unwrap_or_default();
-                    let account: AccountSharedData = account.into();
-                    e.insert(account.clone());
-                    return Some(account);
-                }
+        // Transactions that fail before execution, e.g. because they do not sanitize or the fee payer
+        // cannot pay, are a result like any other. Bad input and RPC failures abort the run.
+        let res = match self.execute(&mut sbf, slot, scenario.as_ref(), source) {
+            Err(e) if !e.is::<TransactionError>() => throw!(e),
+            res => res,
+        };
+
+        let failures = match scenario
+            .as_ref()
+            .and_then(|scenario| scenario.expect.as_ref())
+        {
+            Some(expect) => expect.check(&res),
+            None => vec![],
+        };
+        self.print(slot, res)?;
+
+        for failure in &failures {
+            error!("Expectation failed: {}", failure);
+        }
+        if !failures.is_empty() {
+            throw!(anyhow!("{} expectation(s) failed", failures.len()));
+        }
+    }
+
+    // Loads and processes the message, Err if it failed before execution
+    #[throws(Error)]
+    fn execute(
+        &self,
+        sbf: &mut MessageExecutor,
+        slot: Slot,
+        scenario: Option<&Scenario>,
+        mut source: RpcAccountSource,
+    ) -> ExecutionRecord {
+        let message = self.message(sbf, scenario, &mut source)?;
+
+        let working_slot = sbf.working_slot(slot);
+        source.prefetch(&message);
+        let mut loader = sbf.loader(source);
+        let (loaded_transaction, loaded_programs) = loader.load(&working_slot, &message)?;
+        // Source failures abort the load above, so only accounts that do not exist are left
+        for missing in loader.missing_accounts() {
+            warn!("{} not found, loaded as an empty account", missing.pubkey());
+        }
+
+        sbf.process(slot, &message, loaded_transaction, &loaded_programs)?
+    }
+
+    #[throws(Error)]
+    fn print(&self, slot: Slot, res: Result<ExecutionRecord, Error>) {
+        if self.output == Output::Json {
+            let value = match res {
+                Ok(record) => record.to_rpc_simulate_result(Some(&record.keys)),
+                Err(e) => match e.downcast::<TransactionError>() {
+                    Ok(e) => RpcSimulateTransactionResult::from_error(e),
+                    Err(e) => throw!(e),
+                },
+            };
+            let response = Response {
+                context: RpcResponseContext::new(slot),
+                value,
+            };
+            println!("{}", serde_json::to_string_pretty(&response)?);
+            return;
+        }
+
+        let record = match res {
+            // Nothing was executed
+            Err(e) => {
+                error!("Invoke errored: {}", e);
+                return;
+            }
+            Ok(record) => record,
+        };
+
+        info!(
+            "Fee: {}, Rent: {}, CU: {}",
+            record.fee, record.rent, record.cu
+        );
+        for inner_instructions in &record.inner_instructions {
+            for inner in &inner_instructions.instructions {
+                info!(
+                    "{}Inner instruction of #{}: program {}, CU: {:?}",
+                    "  ".repeat(inner.stack_height as usize - 1),
+                    inner_instructions.index,
+                    record.keys[inner.instruction.program_id_index as usize],
+                    inner.compute_units_consumed
+                );
+            }
+        }
+        for diff in record.diff() {
+            info!(
+                "Account {}: lamports {:+}, owner {:?}, data len {:?}, changed bytes {:?}",
+                diff.pubkey,
+                diff.lamports_delta(),
+                diff.owner,
+                diff.data_len,
+                diff.changed_ranges
+            );
+            if let Some(token_balance) = &diff.token_balance {
+                info!(
+                    "  Token balance of {} for mint {}: {:?} -> {:?} ({:+}), decimals {:?}",
+                    token_balance.owner,
+                    token_balance.mint,
+                    token_balance.pre_amount,
+                    token_balance.post_amount,
+                    token_balance.amount_delta(),
+                    token_balance.decimals
+                );
             }
-        });
+        }
+        if let Some(return_data) = &record.return_data {
+            info!(
+                "Return data from {}: {}",
+                return_data.program_id,
+                bs58::encode(&return_data.data).into_string()
+            );
+        }
+        match record.result() {
+            Err(e) => error!("Invoke errored: {}:\nLogs: {:?}", e, record.logs),
+            Ok(()) => info!("{:?}", record.logs),
+        }
+    }
+
+    // The message to simulate, with address table lookups resolved through `source`
+    #[throws(Error)]
+    fn message<G>(
+        &self,
+        sbf: &MessageExecutor,
+        scenario: Option<&Scenario>,
+        source: &mut G,
+    ) -> SanitizedMessage
+    where
+        G: AccountSource,
+    {
+        let message = if let Some(transaction) = &self.transaction {
+            let tx: VersionedTransaction = decode(transaction)?;
+            let tables = lookup_tables(&tx.message, source)?;
+            sbf.sanitize_transaction(tx, sbf.lookup_table_loader(|key| tables.get(key).cloned()))?
+                .message()
+                .clone()
+        } else if let Some(path) = &self.transaction_file {
+            let mut data = vec![];
+            File::open(path)?.read_to_end(&mut data)?;
+            let tx: VersionedTransaction = decode_file(&data)?;
+            let tables = lookup_tables(&tx.message, source)?;
+            sbf.sanitize_transaction(tx, sbf.lookup_table_loader(|key| tables.get(key).cloned()))?
+                .message()
+                .clone()
+        } else if let Some(message) = &self.message {
+            let message: VersionedMessage = decode(message)?;
+            let tables = lookup_tables(&message, source)?;
+            let loader = sbf.lookup_table_loader(|key| tables.get(key).cloned());
+            SanitizedMessage::try_new(message.try_into()?, loader)?
+        } else if let Some(scenario) = scenario {
+            scenario.message()?
+        } else {
+            return self.instruction_message()?;
+        };
 
-        let ix_data = bs58::decode(&self.instruction).into_vec()?;
+        if self.signer_account.is_empty() {
+            message
+        } else {
+            sbf.mark_signers(&message, &self.signer_account)?
+        }
+    }
+
+    #[throws(Error)]
+    fn instruction_message(&self) -> SanitizedMessage {
+        let ix_data = bs58::decode(self.instruction.as_deref().unwrap_or_default()).into_vec()?;
         let ix = Instruction::new_with_bytes(
             self.program_id,
             &ix_data,
@@ -157,24 +513,91 @@ impl Simulate {
                 })
                 .collect(),
         );
-        let message = Message::new(&[ix], None);
-        let message = SanitizedMessage::Legacy(LegacyMessage::new(message));
-        let loaded_transaction = loader.load_transaction_accounts(&message)?;
-        let loaded_programs = loader.replenish_program_cache(&WorkingSlot(slot), [&message])?;
-
-        sbf.record_log();
-        let res = sbf.process(slot, &message, loaded_transaction, &loaded_programs);
-
-        if let Err(e) = res {
-            error!(
-                "Invoke errored: {}:\nLogs: {:?}",
-                e,
-                sbf.logger().get_recorded_content()
-            );
-        } else {
-            info!("{:?}", sbf.logger().get_recorded_content());
+        let message = Message::new(&[ix], self.fee_payer.as_ref());
+        SanitizedMessage::Legacy(LegacyMessage::new(message))
+    }
+}
+
+// A JSON dump as printed by `solana account --output json` or a bare UiAccount,
+// otherwise the data of a rent exempt system account in base64 or raw bytes
+#[throws(Error)]
+fn read_account(path: &Path, rent: &Rent) -> AccountSharedData {
+    let bytes = fs::read(path)?;
+    if let Ok(json) = serde_json::from_slice::<Value>(&bytes) {
+        let account: UiAccount =
+            serde_json::from_value(json.get("account").unwrap_or(&json).clone())?;
+        return account
+            .decode()
+            .ok_or_else(|| anyhow!("unsupported data encoding in {}", path.display()))?;
+    }
+
+    let data = std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|text| BASE64_STANDARD.decode(text.trim()).ok())
+        .unwrap_or(bytes);
+    let mut account = AccountSharedData::new(
+        rent.minimum_balance(data.len()),
+        data.len(),
+        &system_program::id(),
+    );
+    account.set_data_from_slice(&data);
+    account
+}
+
+fn parse_key_value<T>(s: &str) -> Result<(Pubkey, T), Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected <pubkey>=<value>"))?;
+    let value = value.parse().map_err(|e| anyhow!("{}: {}", value, e))?;
+    let key = key.parse().map_err(|e| anyhow!("{}: {}", key, e))?;
+    Ok((key, value))
+}
+
+// The address lookup tables of `message` that exist, fetched before sanitizing as an AddressLoader
+// cannot report why a table is unavailable
+#[throws(AccountLoadError)]
+fn lookup_tables<G>(
+    message: &VersionedMessage,
+    source: &mut G,
+) -> HashMap<Pubkey, AccountSharedData>
+where
+    G: AccountSource,
+{
+    let mut tables = HashMap::new();
+    for lookup in message.address_table_lookups().unwrap_or_default() {
+        if let Some(account) = source.get_account(&lookup.account_key)? {
+            tables.insert(lookup.account_key, account);
         }
     }
+    tables
+}
+
+// Base58 first: its alphabet is a subset of base64, so most base58 strings are also valid base64
+#[throws(Error)]
+fn decode<T: DeserializeOwned>(data: &str) -> T {
+    let data = data.trim();
+    let from_base58 = bs58::decode(data)
+        .into_vec()
+        .ok()
+        .and_then(|bytes| bincode::deserialize(&bytes).ok());
+    match from_base58 {
+        Some(value) => value,
+        None => bincode::deserialize(&BASE64_STANDARD.decode(data)?)?,
+    }
+}
+
+// Raw bytes can be valid UTF-8 too, so they are tried when the file does not decode as text
+#[throws(Error)]
+fn decode_file<T: DeserializeOwned>(data: &[u8]) -> T {
+    match std::str::from_utf8(data).map(decode) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => bincode::deserialize(data).map_err(|_| e)?,
+        Err(_) => bincode::deserialize(data)?,
+    }
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -214,3 +637,150 @@ impl GetProgramData {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use clap::{error::ErrorKind, Parser};
+    use solana_sdk::{
+        account::{AccountSharedData, ReadableAccount},
+        bpf_loader_upgradeable,
+        hash::Hash,
+        message::{Message, MessageHeader, VersionedMessage},
+        pubkey::Pubkey,
+        rent::Rent,
+        signature::Signature,
+        system_program,
+        transaction::VersionedTransaction,
+    };
+
+    use super::{decode, decode_file, parse_key_value, read_account, Cli};
+
+    fn transaction(key: Pubkey, blockhash: Hash) -> VersionedTransaction {
+        VersionedTransaction {
+            signatures: vec![],
+            message: VersionedMessage::Legacy(Message {
+                header: MessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 0,
+                },
+                account_keys: vec![key],
+                recent_blockhash: blockhash,
+                instructions: vec![],
+            }),
+        }
+    }
+
+    #[test]
+    fn decodes_base58_and_base64() {
+        let mut tx = transaction(Pubkey::new_unique(), Hash::new_unique());
+        tx.signatures.push(Signature::new_unique());
+        let bytes = bincode::serialize(&tx).unwrap();
+
+        let base58 = bs58::encode(&bytes).into_string();
+        assert_eq!(decode::<VersionedTransaction>(&base58).unwrap(), tx);
+        let base64 = BASE64_STANDARD.encode(&bytes);
+        assert_eq!(
+            decode::<VersionedTransaction>(&format!(" {base64}\n")).unwrap(),
+            tx
+        );
+        assert!(decode::<VersionedTransaction>("not a transaction").is_err());
+    }
+
+    #[test]
+    fn decodes_raw_files_that_are_valid_utf8() {
+        let tx = transaction(
+            Pubkey::new_from_array([b'a'; 32]),
+            Hash::new_from_array([b'b'; 32]),
+        );
+        let bytes = bincode::serialize(&tx).unwrap();
+        assert!(std::str::from_utf8(&bytes).is_ok());
+
+        assert_eq!(decode_file::<VersionedTransaction>(&bytes).unwrap(), tx);
+        let base58 = bs58::encode(&bytes).into_string();
+        assert_eq!(
+            decode_file::<VersionedTransaction>(base58.as_bytes()).unwrap(),
+            tx
+        );
+    }
+
+    #[test]
+    fn instruction_arguments_conflict_with_transactions() {
+        let payer = Pubkey::new_unique().to_string();
+        for flag in ["--fee-payer", "--account", "--writable-account"] {
+            let args = ["simulator", "simulate", "--transaction", "tx", flag, &payer];
+            let e = Cli::try_parse_from(args).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::ArgumentConflict);
+        }
+
+        let args = [
+            "simulator",
+            "simulate",
+            "--instruction",
+            "1",
+            "--fee-payer",
+            &payer,
+        ];
+        assert!(Cli::try_parse_from(args).is_ok());
+    }
+
+    #[test]
+    fn parses_key_value_pairs() {
+        let key = Pubkey::new_unique();
+        assert_eq!(
+            parse_key_value::<u64>(&format!("{key}=42")).unwrap(),
+            (key, 42)
+        );
+        assert_eq!(
+            parse_key_value::<PathBuf>(&format!("{key}=dir/a=b.json")).unwrap(),
+            (key, PathBuf::from("dir/a=b.json"))
+        );
+        assert!(parse_key_value::<u64>(&key.to_string()).is_err());
+        assert!(parse_key_value::<u64>(&format!("{key}=lots")).is_err());
+        assert!(parse_key_value::<u64>("not a pubkey=42").is_err());
+    }
+
+    #[test]
+    fn reads_account_dumps_and_data() {
+        let rent = Rent::default();
+        let path = std::env::temp_dir().join(format!("{}.account", Pubkey::new_unique()));
+        let read = |contents: &[u8]| -> AccountSharedData {
+            fs::write(&path, contents).unwrap();
+            let account = read_account(&path, &rent).unwrap();
+            fs::remove_file(&path).unwrap();
+            account
+        };
+
+        // As printed by `solana account --output json`, and the bare account
+        let owner = bpf_loader_upgradeable::id();
+        let ui_account = format!(
+            r#"{{"lamports": 42, "data": ["AQID", "base64"], "owner": "{owner}",
+            "executable": true, "rentEpoch": 7, "space": 3}}"#
+        );
+        let dump = format!(
+            r#"{{"pubkey": "{}", "account": {ui_account}}}"#,
+            Pubkey::new_unique()
+        );
+        for json in [dump, ui_account] {
+            let account = read(json.as_bytes());
+            assert_eq!(account.lamports(), 42);
+            assert_eq!(account.data(), &[1, 2, 3]);
+            assert_eq!(account.owner(), &owner);
+            assert!(account.executable());
+            assert_eq!(account.rent_epoch(), 7);
+        }
+
+        // Otherwise the data of a rent exempt system account
+        for contents in [&b"AQID\n"[..], &[1, 2, 3]] {
+            let account = read(contents);
+            assert_eq!(account.data(), &[1, 2, 3]);
+            assert_eq!(account.owner(), &system_program::id());
+            assert_eq!(account.lamports(), rent.minimum_balance(3));
+        }
+        let account = read(&[0xff, 0xfe]);
+        assert_eq!(account.data(), &[0xff, 0xfe]);
+    }
+}
@@ -0,0 +1,117 @@
+use solana_sdk::{
+    account::{AccountSharedData, WritableAccount},
+    native_loader,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+};
+use spl_token_2022::state::{Account as TokenAccount, AccountState, Mint};
+
+// Synthetic accounts for what-if simulations, e.g. a vault holding a balance it does not have on chain
+
+// A wallet holding `lamports`
+pub fn system_account(lamports: u64) -> AccountSharedData {
+    AccountSharedData::new(lamports, 0, &system_program::id())
+}
+
+// A native program account as created in genesis: executable, owned by the native loader, with the
+// program name as data
+pub fn native_program_account(name: &str) -> AccountSharedData {
+    let mut account = AccountSharedData::new(1, name.len(), &native_loader::id());
+    account.data_as_mut_slice().copy_from_slice(name.as_bytes());
+    account.set_executable(true);
+    account
+}
+
+// An initialized mint of SPL Token or Token-2022 (without extensions), rent exempt
+pub fn token_mint_account(
+    token_program: &Pubkey,
+    mint_authority: Option<Pubkey>,
+    supply: u64,
+    decimals: u8,
+    rent: &Rent,
+) -> AccountSharedData {
+    let mint = Mint {
+        mint_authority: mint_authority.into(),
+        supply,
+        decimals,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    let mut account =
+        AccountSharedData::new(rent.minimum_balance(Mint::LEN), Mint::LEN, token_program);
+    Mint::pack_into_slice(&mint, account.data_as_mut_slice());
+    account
+}
+
+// An initialized token account of SPL Token or Token-2022 (without extensions) holding `amount`, rent exempt
+pub fn token_account(
+    token_program: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+    rent: &Rent,
+) -> AccountSharedData {
+    let state = TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    };
+    let mut account = AccountSharedData::new(
+        rent.minimum_balance(TokenAccount::LEN),
+        TokenAccount::LEN,
+        token_program,
+    );
+    TokenAccount::pack_into_slice(&state, account.data_as_mut_slice());
+    account
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_accounts_db::inline_spl_token;
+    use solana_sdk::{
+        account::ReadableAccount, native_loader, program_option::COption, program_pack::Pack,
+        pubkey::Pubkey, rent::Rent,
+    };
+    use spl_token_2022::state::{Account as TokenAccount, AccountState, Mint};
+
+    use super::{native_program_account, token_account, token_mint_account};
+
+    #[test]
+    fn native_program_accounts_are_executable() {
+        let account = native_program_account("solana_system_program");
+        assert!(account.executable());
+        assert_eq!(account.owner(), &native_loader::id());
+        assert_eq!(account.data(), b"solana_system_program");
+    }
+
+    #[test]
+    fn token_accounts_of_both_token_programs() {
+        let rent = Rent::default();
+        let (mint, owner, authority) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        for token_program in [inline_spl_token::id(), spl_token_2022::id()] {
+            let account = token_mint_account(&token_program, Some(authority), 1_000, 6, &rent);
+            assert_eq!(account.owner(), &token_program);
+            assert!(rent.is_exempt(account.lamports(), account.data().len()));
+            let state = Mint::unpack(account.data()).unwrap();
+            assert_eq!(state.mint_authority, COption::Some(authority));
+            assert_eq!((state.supply, state.decimals), (1_000, 6));
+            assert!(state.is_initialized);
+
+            let account = token_account(&token_program, &mint, &owner, 400, &rent);
+            assert_eq!(account.owner(), &token_program);
+            assert!(rent.is_exempt(account.lamports(), account.data().len()));
+            let state = TokenAccount::unpack(account.data()).unwrap();
+            assert_eq!((state.mint, state.owner, state.amount), (mint, owner, 400));
+            assert_eq!(state.state, AccountState::Initialized);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use solana_sdk::{
+    account::{AccountSharedData, ReadableAccount},
+    address_lookup_table::{self, state::AddressLookupTable},
+    message::{
+        v0::{LoadedAddresses, MessageAddressTableLookup},
+        AddressLoader, AddressLoaderError,
+    },
+    pubkey::Pubkey,
+    slot_hashes::SlotHashes,
+    slot_history::Slot,
+};
+
+use super::MessageExecutor;
+
+// Resolves the address table lookups of v0 messages like Bank::load_addresses, reading the tables from `g`
+#[derive(Clone)]
+pub struct LookupTableLoader<G> {
+    g: G,
+    slot: Slot,
+    slot_hashes: Arc<SlotHashes>,
+}
+
+impl MessageExecutor {
+    // Lookup tables are resolved at the slot of the clock sysvar, as deactivation depends on the slot hashes
+    pub fn lookup_table_loader<G>(&self, g: G) -> LookupTableLoader<G>
+    where
+        G: Fn(&Pubkey) -> Option<AccountSharedData> + Clone,
+    {
+        LookupTableLoader {
+            g,
+            slot: self
+                .sysvar_cache()
+                .get_clock()
+                .map(|clock| clock.slot)
+                .unwrap_or_default(),
+            slot_hashes: self.sysvar_cache().get_slot_hashes().unwrap_or_default(),
+        }
+    }
+}
+
+impl<G> AddressLoader for LookupTableLoader<G>
+where
+    G: Fn(&Pubkey) -> Option<AccountSharedData> + Clone,
+{
+    fn load_addresses(
+        self,
+        lookups: &[MessageAddressTableLookup],
+    ) -> Result<LoadedAddresses, AddressLoaderError> {
+        lookups
+            .iter()
+            .map(|lookup| {
+                let account = (self.g)(&lookup.account_key)
+                    .ok_or(AddressLoaderError::LookupTableAccountNotFound)?;
+                if !address_lookup_table::program::check_id(account.owner()) {
+                    return Err(AddressLoaderError::InvalidAccountOwner);
+                }
+                let table = AddressLookupTable::deserialize(account.data())
+                    .map_err(|_| AddressLoaderError::InvalidAccountData)?;
+
+                Ok(LoadedAddresses {
+                    writable: table.lookup(
+                        self.slot,
+                        &lookup.writable_indexes,
+                        &self.slot_hashes,
+                    )?,
+                    readonly: table.lookup(
+                        self.slot,
+                        &lookup.readonly_indexes,
+                        &self.slot_hashes,
+                    )?,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::{HashMap, HashSet};
+
+use solana_runtime::builtins::{BuiltinPrototype, BUILTINS};
+use solana_sdk::{feature_set::FeatureSet, pubkey::Pubkey};
+
+// The builtin programs an executor registers, see MessageExecutor::new_with_builtins. By default
+// every entry of BUILTINS whose feature is active, like Bank::apply_builtin_program_feature_transitions.
+#[derive(Debug, Clone, Default)]
+pub struct BuiltinSet {
+    disabled: HashSet<Pubkey>,
+    substitutes: HashMap<Pubkey, Vec<u8>>, // ELFs replacing builtins, like a migration to core BPF
+}
+
+impl BuiltinSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Invoking the program then fails with UnsupportedProgramId
+    pub fn disable(mut self, program_id: Pubkey) -> Self {
+        self.disabled.insert(program_id);
+        self
+    }
+
+    // Runs `elf` as an upgradeable loader program at the address of the builtin, whatever the
+    // account source holds for it
+    pub fn substitute(mut self, program_id: Pubkey, elf: Vec<u8>) -> Self {
+        self.substitutes.insert(program_id, elf);
+        self
+    }
+
+    pub fn substitutes(&self) -> &HashMap<Pubkey, Vec<u8>> {
+        &self.substitutes
+    }
+
+    // The builtins that are registered under `feature_set`
+    pub fn active<'a>(
+        &'a self,
+        feature_set: &'a FeatureSet,
+    ) -> impl Iterator<Item = &'static BuiltinPrototype> + 'a {
+        BUILTINS.iter().filter(|builtin| {
+            let enabled = match builtin.feature_id {
+                Some(feature_id) => feature_set.is_active(&feature_id),
+                None => true, // since genesis
+            };
+            enabled
+                && !self.disabled.contains(&builtin.program_id)
+                && !self.substitutes.contains_key(&builtin.program_id)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_sdk::{
+        instruction::InstructionError, pubkey::Pubkey, system_instruction, system_program,
+        transaction::TransactionError,
+    };
+
+    use super::{
+        super::{
+            system_account,
+            test_utils::{feature_set, message, run, SLOT},
+            MessageExecutor,
+        },
+        BuiltinSet,
+    };
+
+    #[test]
+    fn disabled_builtin_is_unsupported() {
+        let builtins = BuiltinSet::new().disable(system_program::id());
+        let mut sbf = MessageExecutor::new_with_builtins(feature_set(), &builtins).unwrap();
+        sbf.set_synthetic_sysvars(SLOT, 0);
+        assert!(sbf
+            .active_builtins()
+            .all(|builtin| builtin.program_id != system_program::id()));
+
+        let (payer, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        let ix = system_instruction::transfer(&payer, &recipient, 1_000_000);
+        let record = run(&mut sbf, &message(&[ix], &payer), &accounts).unwrap();
+        assert_eq!(
+            record.status,
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::UnsupportedProgramId
+            ))
+        );
+    }
+}
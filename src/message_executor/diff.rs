@@ -0,0 +1,252 @@
+use std::ops::Range;
+
+use solana_sdk::{
+    account::{AccountSharedData, ReadableAccount},
+    pubkey::Pubkey,
+};
+use spl_token_2022::{
+    check_spl_token_program_account,
+    extension::StateWithExtensions,
+    state::{Account as TokenAccount, Mint},
+};
+
+use super::ExecutionRecord;
+
+// What an execution changed in an account
+#[derive(Debug, Clone)]
+pub struct AccountDiff {
+    pub pubkey: Pubkey,
+    pub pre_lamports: u64,
+    pub post_lamports: u64,
+    pub owner: Option<(Pubkey, Pubkey)>, // pre and post owner if changed
+    pub data_len: Option<(usize, usize)>, // pre and post length if changed
+    pub changed_ranges: Vec<Range<usize>>, // byte ranges of the data that differ
+    pub token_balance: Option<TokenBalanceDiff>,
+}
+
+// Like the pre/post token balances of RPC transaction metadata
+#[derive(Debug, Clone)]
+pub struct TokenBalanceDiff {
+    pub mint: Pubkey,
+    pub owner: Pubkey,
+    pub pre_amount: Option<u64>, // None if it was not a token account before
+    pub post_amount: Option<u64>,
+    pub decimals: Option<u8>, // None if the mint is not part of the transaction
+}
+
+impl AccountDiff {
+    pub fn lamports_delta(&self) -> i128 {
+        self.post_lamports as i128 - self.pre_lamports as i128
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pre_lamports == self.post_lamports
+            && self.owner.is_none()
+            && self.data_len.is_none()
+            && self.changed_ranges.is_empty()
+    }
+}
+
+impl TokenBalanceDiff {
+    pub fn amount_delta(&self) -> i128 {
+        self.post_amount.unwrap_or_default() as i128 - self.pre_amount.unwrap_or_default() as i128
+    }
+}
+
+impl ExecutionRecord {
+    // The accounts of the message that changed, including the fee payer debit
+    pub fn diff(&self) -> Vec<AccountDiff> {
+        self.keys
+            .iter()
+            .zip(self.pre_datas.iter().zip(&self.datas))
+            .map(|(pubkey, (pre, post))| self.diff_account(pubkey, pre, post))
+            .filter(|diff| !diff.is_empty())
+            .collect()
+    }
+
+    fn diff_account(
+        &self,
+        pubkey: &Pubkey,
+        pre: &AccountSharedData,
+        post: &AccountSharedData,
+    ) -> AccountDiff {
+        let pre_token = token_account(pre);
+        let post_token = token_account(post);
+        let token_balance = post_token
+            .or(pre_token)
+            .map(|(mint, owner, _)| TokenBalanceDiff {
+                mint,
+                owner,
+                pre_amount: pre_token.map(|(_, _, amount)| amount),
+                post_amount: post_token.map(|(_, _, amount)| amount),
+                decimals: self.mint_decimals(&mint),
+            });
+
+        AccountDiff {
+            pubkey: *pubkey,
+            pre_lamports: pre.lamports(),
+            post_lamports: post.lamports(),
+            owner: (pre.owner() != post.owner()).then_some((*pre.owner(), *post.owner())),
+            data_len: (pre.data().len() != post.data().len())
+                .then_some((pre.data().len(), post.data().len())),
+            changed_ranges: changed_ranges(pre.data(), post.data()),
+            token_balance: token_balance.filter(|_| pre_token != post_token),
+        }
+    }
+
+    fn mint_decimals(&self, mint: &Pubkey) -> Option<u8> {
+        let index = self.keys.iter().position(|key| key == mint)?;
+        let account = self.datas.get(index)?;
+        check_spl_token_program_account(account.owner()).ok()?;
+        StateWithExtensions::<Mint>::unpack(account.data())
+            .ok()
+            .map(|mint| mint.base.decimals)
+    }
+}
+
+// (mint, owner, amount) of an SPL Token or Token-2022 account
+fn token_account(account: &AccountSharedData) -> Option<(Pubkey, Pubkey, u64)> {
+    check_spl_token_program_account(account.owner()).ok()?;
+    let state = StateWithExtensions::<TokenAccount>::unpack(account.data()).ok()?;
+    Some((state.base.mint, state.base.owner, state.base.amount))
+}
+
+// Contiguous ranges of differing bytes. Bytes past the end of the shorter data count as changed.
+fn changed_ranges(pre: &[u8], post: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = vec![];
+    for i in 0..pre.len().max(post.len()) {
+        if pre.get(i) == post.get(i) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(range) if range.end == i => range.end = i + 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_accounts_db::{inline_spl_token, rent_debits::RentDebits};
+    use solana_program_runtime::loaded_programs::LoadedProgramsForTxBatch;
+    use solana_sdk::{
+        account::{AccountSharedData, WritableAccount},
+        pubkey::Pubkey,
+        rent::Rent,
+        system_program,
+    };
+
+    use super::{
+        super::{
+            test_utils::{executor, message, run},
+            token_account, token_mint_account, ExecutionRecord,
+        },
+        changed_ranges,
+    };
+
+    fn record(
+        keys: Vec<Pubkey>,
+        pre_datas: Vec<AccountSharedData>,
+        datas: Vec<AccountSharedData>,
+    ) -> ExecutionRecord {
+        ExecutionRecord {
+            keys,
+            pre_datas,
+            datas,
+            cu: 0,
+            fee: 0,
+            rent: 0,
+            rent_debits: RentDebits::default(),
+            tombstones: vec![],
+            programs_modified: LoadedProgramsForTxBatch::default(),
+            inner_instructions: vec![],
+            return_data: None,
+            logs: vec![],
+            status: Ok(()),
+        }
+    }
+
+    #[test]
+    fn changed_ranges_merge_adjacent_bytes() {
+        assert!(changed_ranges(&[1, 2, 3], &[1, 2, 3]).is_empty());
+        assert_eq!(
+            changed_ranges(&[1, 2, 3, 4], &[0, 0, 3, 0]),
+            vec![0..2, 3..4]
+        );
+        assert_eq!(changed_ranges(&[1, 2], &[1, 2, 0, 0]), vec![2..4]);
+        assert_eq!(changed_ranges(&[1, 2, 3], &[0]), vec![0..3]);
+    }
+
+    #[test]
+    fn token_balances_of_both_token_programs() {
+        let rent = Rent::default();
+        for token_program in [inline_spl_token::id(), spl_token_2022::id()] {
+            let (mint, owner, source, destination) = (
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+            );
+            let account = |amount| token_account(&token_program, &mint, &owner, amount, &rent);
+            let record = record(
+                vec![mint, source, destination],
+                vec![
+                    token_mint_account(&token_program, None, 1_000, 6, &rent),
+                    account(1_000),
+                    AccountSharedData::new(0, 0, &system_program::id()),
+                ],
+                vec![
+                    token_mint_account(&token_program, None, 1_000, 6, &rent),
+                    account(400),
+                    account(600),
+                ],
+            );
+
+            let diff = record.diff();
+            assert_eq!(diff.len(), 2);
+            let source_balance = diff[0].token_balance.as_ref().unwrap();
+            assert_eq!((source_balance.mint, source_balance.owner), (mint, owner));
+            assert_eq!(
+                (source_balance.pre_amount, source_balance.post_amount),
+                (Some(1_000), Some(400))
+            );
+            assert_eq!(source_balance.decimals, Some(6));
+            assert_eq!(source_balance.amount_delta(), -600);
+
+            // Created by the transaction
+            let destination_balance = diff[1].token_balance.as_ref().unwrap();
+            assert_eq!(diff[1].owner, Some((system_program::id(), token_program)));
+            assert_eq!(
+                (
+                    destination_balance.pre_amount,
+                    destination_balance.post_amount
+                ),
+                (None, Some(600))
+            );
+            assert_eq!(destination_balance.amount_delta(), 600);
+        }
+    }
+
+    #[test]
+    fn rent_is_part_of_the_diff() {
+        let mut sbf = executor();
+        let payer = Pubkey::new_unique();
+        let mut account = AccountSharedData::new(500_000, 0, &system_program::id());
+        account.set_rent_epoch(0);
+        let accounts = HashMap::from([(payer, account)]);
+
+        let record = run(&mut sbf, &message(&[], &payer), &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+        assert!(record.rent > 0);
+        let diff = record.diff();
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].pre_lamports, 500_000);
+        assert_eq!(
+            diff[0].lamports_delta(),
+            -((record.rent + record.fee) as i128)
+        );
+    }
+}
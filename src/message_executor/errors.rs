@@ -1,4 +1,70 @@
+use solana_sdk::{pubkey::Pubkey, transaction::TransactionError};
 use thiserror::Error;
 
 #[derive(Debug, Clone, Error)]
-pub enum MessageExecutorError {}
+pub enum MessageExecutorError {
+    #[error("Signers cannot be marked when signature verification is enabled")]
+    SigVerifyEnabled,
+    #[error("{0} is loaded from an address lookup table and cannot sign")]
+    LookedUpSigner(Pubkey),
+    #[error("{error}, tombstoned programs: {}", display_tombstones(.tombstones))]
+    TombstonedPrograms {
+        error: TransactionError,
+        tombstones: Vec<(Pubkey, ProgramLoadError)>,
+    },
+    #[error("Accounts could not be loaded: {}", display_load_errors(.0))]
+    AccountsUnavailable(Vec<AccountLoadError>),
+}
+
+// Why an account source did not provide an account
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AccountLoadError {
+    #[error("{0}: not found")]
+    NotFound(Pubkey),
+    #[error("{pubkey}: {reason}")]
+    SourceFailure { pubkey: Pubkey, reason: String },
+}
+
+impl AccountLoadError {
+    pub fn pubkey(&self) -> &Pubkey {
+        match self {
+            AccountLoadError::NotFound(pubkey) => pubkey,
+            AccountLoadError::SourceFailure { pubkey, .. } => pubkey,
+        }
+    }
+}
+
+// Why a program resolved to a tombstone in the program cache
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ProgramLoadError {
+    #[error("Program account is closed or missing")]
+    Closed,
+    #[error("Program is not visible until the slot after its deployment")]
+    DelayVisibility,
+    #[error("Program account data is invalid")]
+    InvalidAccountData,
+    #[error("Programdata account header is invalid")]
+    InvalidProgramData,
+    #[error("Loader-v4 program is retracted or its state is invalid")]
+    InvalidV4Program,
+    #[error("ELF rejected: {0}")]
+    Rejected(String),
+    #[error("Program failed verification")]
+    FailedVerification,
+}
+
+fn display_tombstones(tombstones: &[(Pubkey, ProgramLoadError)]) -> String {
+    tombstones
+        .iter()
+        .map(|(program_id, reason)| format!("{}: {}", program_id, reason))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn display_load_errors(errors: &[AccountLoadError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,53 +1,124 @@
 use std::{
-    cell::{Ref, RefCell},
-    collections::HashSet,
-    rc::Rc,
+    collections::{HashMap, HashSet},
     sync::Arc,
 };
 
 use anyhow::Error;
-use fehler::throws;
-use getset::{Getters, MutGetters};
-use solana_accounts_db::accounts::LoadedTransaction;
+use fehler::{throw, throws};
+use getset::{CopyGetters, Getters, MutGetters, Setters};
+use solana_accounts_db::{
+    accounts::LoadedTransaction, rent_collector::RentCollector, rent_debits::RentDebits,
+};
 use solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1;
 use solana_loader_v4_program::create_program_runtime_environment_v2;
 use solana_program_runtime::{
-    compute_budget::ComputeBudget,
-    loaded_programs::{LoadedProgram, LoadedPrograms, LoadedProgramsForTxBatch},
+    compute_budget::{self, ComputeBudget},
+    loaded_programs::{LoadedProgram, LoadedProgramType, LoadedPrograms, LoadedProgramsForTxBatch},
     log_collector::LogCollector,
-    message_processor::MessageProcessor,
     sysvar_cache::SysvarCache,
     timings::ExecuteTimings,
 };
-use solana_runtime::builtins::BUILTINS;
+use solana_runtime::builtins::{BuiltinPrototype, BUILTINS};
 use solana_sdk::{
-    account::AccountSharedData, bpf_loader, bpf_loader_deprecated, bpf_loader_upgradeable,
-    epoch_schedule::DEFAULT_SLOTS_PER_EPOCH, feature_set::FeatureSet, loader_v4,
-    message::SanitizedMessage, pubkey::Pubkey, rent::Rent, slot_history::Slot,
-    transaction_context::TransactionContext,
+    account::{AccountSharedData, ReadableAccount, WritableAccount},
+    bpf_loader, bpf_loader_deprecated, bpf_loader_upgradeable,
+    epoch_schedule::{EpochSchedule, DEFAULT_SLOTS_PER_EPOCH},
+    feature_set::{
+        add_set_tx_loaded_accounts_data_size_instruction, checked_arithmetic_in_fee_validation,
+        enable_early_verification_of_account_modifications,
+        include_loaded_accounts_data_size_in_fee_calculation,
+        remove_congestion_multiplier_from_fee_calculation, remove_deprecated_request_unit_ix,
+        FeatureSet,
+    },
+    fee::FeeStructure,
+    instruction::{CompiledInstruction, InstructionError},
+    loader_v4,
+    message::{
+        v0::{self, LoadedMessage},
+        AddressLoader, LegacyMessage, Message, MessageHeader, SanitizedMessage,
+    },
+    nonce,
+    pubkey::Pubkey,
+    rent::Rent,
+    slot_history::Slot,
+    transaction::{MessageHash, SanitizedTransaction, TransactionError, VersionedTransaction},
+    transaction_context::{IndexOfAccount, TransactionContext, TransactionReturnData},
 };
+use solana_system_program::{get_system_account_kind, SystemAccountKind};
 
-use super::{AccountLoader, ForkGraph};
+use super::{
+    accounts::native_program_account,
+    builtins::BuiltinSet,
+    inner_instructions::{inner_instructions, logs_by_instruction, InnerInstructions},
+    message_processor::process_message,
+    mocks::{self, MockInvocation, MockProgram, MockScope},
+    options::ProcessOptions,
+    programs::upgradeable_program_accounts,
+    AccountLoader, AccountSource, ForkGraph, MessageExecutorError, ProgramLoadError, ProgramUses,
+    WorkingSlot,
+};
 
 #[derive(Debug)]
 pub struct ExecutionRecord {
     pub keys: Vec<Pubkey>,
+    pub pre_datas: Vec<AccountSharedData>, // before rent and the fee were charged, see diff
     pub datas: Vec<AccountSharedData>,
     pub cu: u64,
+    pub fee: u64, // lamports debited from the fee payer, including the prioritization fee
+    pub rent: u64, // rent collected from the writable accounts while loading
+    pub rent_debits: RentDebits,
+    pub tombstones: Vec<(Pubkey, ProgramLoadError)>, // executable accounts that resolved to tombstones
+    pub programs_modified: LoadedProgramsForTxBatch, // deployed, upgraded or closed programs, see commit_programs
+    pub inner_instructions: Vec<InnerInstructions>,
+    pub return_data: Option<TransactionReturnData>,
+    pub logs: Vec<Vec<String>>, // logs split by top-level instruction
+    // Err(InstructionError(index, err)) when an instruction failed. The accounts are left as the failed
+    // instruction left them and must not be committed, the logs and compute units are the ones so far.
+    pub status: Result<(), TransactionError>,
 }
 
-#[derive(Getters, MutGetters)]
+impl ExecutionRecord {
+    // The status as an error, explaining the tombstoned programs if any
+    #[throws(Error)]
+    pub fn result(&self) {
+        if let Err(error) = &self.status {
+            if self.tombstones.is_empty() {
+                throw!(error.clone());
+            }
+            throw!(MessageExecutorError::TombstonedPrograms {
+                error: error.clone(),
+                tombstones: self.tombstones.clone(),
+            });
+        }
+    }
+}
+
+#[derive(Getters, MutGetters, CopyGetters, Setters)]
 pub struct MessageExecutor {
     pub(crate) feature_set: Arc<FeatureSet>,
     #[getset(get_mut = "pub", get = "pub")]
     sysvar_cache: SysvarCache,
-    pub(crate) logger: Option<Rc<RefCell<LogCollector>>>,
     pub(crate) program_owners: HashSet<Pubkey>, // a set of program loaders that owns all the programs (except for native)
     pub(crate) builtin_programs: HashSet<Pubkey>,
+    pub(crate) program_accounts: HashMap<Pubkey, AccountSharedData>, // served instead of the source, for ELFs replacing builtins and mock programs
+    pub(crate) mock_programs: HashMap<Pubkey, Arc<MockProgram>>,
     pub(crate) loaded_programs: LoadedPrograms<ForkGraph>,
+    #[getset(get = "pub")]
+    pub(crate) program_load_errors: HashMap<Pubkey, ProgramLoadError>, // why programs failed to load
+    pub(crate) program_uses: ProgramUses,
+    #[getset(get_mut = "pub", get = "pub")]
+    fee_structure: FeeStructure,
+    #[getset(get_copy = "pub", set = "pub")]
+    sig_verify: bool, // verify signatures and precompiles of sanitized transactions
 }
 
-unsafe impl Send for MessageExecutor {}
+// Checked at compile time instead of asserted with unsafe impls: &MessageExecutor can be shared across
+// threads for read-only calls like process, and records can be sent back, see ExecutorPool
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<MessageExecutor>();
+    assert_send_sync::<ExecutionRecord>();
+};
 
 impl MessageExecutor {
     #[throws(Error)]
@@ -57,6 +128,16 @@ impl MessageExecutor {
             features.activate(&feat, 0);
         }
 
+        Self::new_with_feature_set(features)?
+    }
+
+    #[throws(Error)]
+    pub fn new_with_feature_set(features: FeatureSet) -> Self {
+        Self::new_with_builtins(features, &BuiltinSet::default())?
+    }
+
+    #[throws(Error)]
+    pub fn new_with_builtins(features: FeatureSet, builtins: &BuiltinSet) -> Self {
         let program_owners = HashSet::from_iter(vec![
             loader_v4::id(),
             bpf_loader_upgradeable::id(),
@@ -64,7 +145,10 @@ impl MessageExecutor {
             bpf_loader_deprecated::id(),
         ]);
 
-        let mut loaded_programs = LoadedPrograms::new(DEFAULT_SLOTS_PER_EPOCH, 1);
+        // No epoch schedule is set yet, the root epoch follows the sysvar, see loader
+        let root_slot = DEFAULT_SLOTS_PER_EPOCH;
+        let root_epoch = EpochSchedule::default().get_epoch(root_slot);
+        let mut loaded_programs = LoadedPrograms::new(root_slot, root_epoch);
 
         loaded_programs.environments.program_runtime_v1 = Arc::new(
             create_program_runtime_environment_v1(&features, &Default::default(), false, false)
@@ -77,54 +161,281 @@ impl MessageExecutor {
         let mut this = Self {
             feature_set: Arc::new(features),
             sysvar_cache: SysvarCache::default(),
-            logger: Some(LogCollector::new_ref()),
             program_owners,
             loaded_programs,
+            program_load_errors: HashMap::new(),
+            program_uses: ProgramUses::default(),
             builtin_programs: HashSet::new(),
+            program_accounts: HashMap::new(),
+            mock_programs: HashMap::new(),
+            fee_structure: FeeStructure::default(),
+            sig_verify: false,
         };
 
         // Bank::apply_builtin_program_feature_transitions
-        for builtin in BUILTINS.iter() {
-            let should_apply_action_for_feature_transition = builtin
-                .feature_id
-                .map(|f| this.feature_set.is_active(&f))
-                .unwrap_or(true);
-
-            {
-                if should_apply_action_for_feature_transition {
-                    // debug!("Adding program {} under {:?}", name, program_id);
-                    // self.add_builtin_account(name.as_str(), &program_id, false);
-                    this.builtin_programs.insert(builtin.program_id);
-                    this.loaded_programs.replenish(
-                        builtin.program_id,
-                        Arc::new(LoadedProgram::new_builtin(
-                            0,
-                            builtin.name.len(),
-                            builtin.entrypoint,
-                        )),
-                    );
-                }
-            }
+        for builtin in builtins.active(&this.feature_set) {
+            this.builtin_programs.insert(builtin.program_id);
+            this.loaded_programs.replenish(
+                builtin.program_id,
+                Arc::new(LoadedProgram::new_builtin(
+                    0,
+                    builtin.name.len(),
+                    builtin.entrypoint,
+                )),
+            );
+        }
+
+        // Deployed at genesis, the rent sysvar is not known yet
+        for (program_id, elf) in builtins.substitutes() {
+            this.program_accounts.extend(upgradeable_program_accounts(
+                program_id,
+                elf,
+                None,
+                0,
+                &Rent::default(),
+            )?);
         }
 
         this
     }
 
+    // The registered builtins, see BuiltinSet
+    pub fn active_builtins(&self) -> impl Iterator<Item = &'static BuiltinPrototype> + '_ {
+        BUILTINS
+            .iter()
+            .filter(|builtin| self.builtin_programs.contains(&builtin.program_id))
+    }
+
+    // Registers `program` at `program_id` like a builtin, to be invoked top-level or through CPI
+    // instead of the program on the cluster
+    pub fn add_mock_program<F>(&mut self, program_id: Pubkey, program: F)
+    where
+        F: Fn(&mut MockInvocation) -> Result<(), InstructionError> + Send + Sync + 'static,
+    {
+        self.mock_programs.insert(program_id, Arc::new(program));
+        self.builtin_programs.insert(program_id);
+        self.program_accounts
+            .insert(program_id, native_program_account("mock_program"));
+        self.loaded_programs.replenish(
+            program_id,
+            Arc::new(LoadedProgram::new_builtin(0, 0, mocks::Entrypoint::vm)),
+        );
+    }
+
     pub fn loader<'a, G>(&'a mut self, g: G) -> AccountLoader<'a, G>
     where
-        G: FnMut(&Pubkey) -> Option<AccountSharedData>,
+        G: AccountSource,
     {
+        // The epoch schedule may have changed since the cache was created or pruned
+        let root_slot = self.loaded_programs.latest_root_slot;
+        self.loaded_programs.latest_root_epoch = self.epoch_schedule().get_epoch(root_slot);
         AccountLoader::from_executor(g, self)
     }
 
-    pub fn logger(&self) -> Ref<LogCollector> {
-        self.logger.as_ref().unwrap().borrow()
+    // Roughly the sigverify stage plus Bank::verify_transaction
+    #[throws(TransactionError)]
+    pub fn sanitize_transaction(
+        &self,
+        tx: VersionedTransaction,
+        address_loader: impl AddressLoader,
+    ) -> SanitizedTransaction {
+        self.sanitize_transaction_with_sig_verify(tx, address_loader, self.sig_verify)?
     }
 
-    pub fn record_log(&mut self) {
-        self.logger = Some(LogCollector::new_ref());
+    // Like sanitize_transaction with `sig_verify` instead of the flag of the executor, for callers
+    // that decide per transaction like simulateTransaction
+    #[throws(TransactionError)]
+    pub fn sanitize_transaction_with_sig_verify(
+        &self,
+        tx: VersionedTransaction,
+        address_loader: impl AddressLoader,
+        sig_verify: bool,
+    ) -> SanitizedTransaction {
+        let tx = SanitizedTransaction::try_create(tx, MessageHash::Compute, None, address_loader)?;
+
+        if sig_verify {
+            tx.verify()?;
+            tx.verify_precompiles(&self.feature_set)?;
+        }
+
+        tx
     }
 
+    // The message with the given accounts marked as signers, for what-if analysis. Only the marked
+    // accounts move, to the end of the signers with the same writability, so the other accounts keep
+    // their order. Accounts loaded from address lookup tables cannot sign.
+    #[throws(Error)]
+    pub fn mark_signers(&self, message: &SanitizedMessage, signers: &[Pubkey]) -> SanitizedMessage {
+        if self.sig_verify {
+            throw!(MessageExecutorError::SigVerifyEnabled);
+        }
+
+        match message {
+            SanitizedMessage::Legacy(legacy) => {
+                let mut message = legacy.message.clone().into_owned();
+                let Message {
+                    header,
+                    account_keys,
+                    instructions,
+                    ..
+                } = &mut message;
+                for signer in signers {
+                    move_to_signers(header, account_keys, instructions, signer);
+                }
+                SanitizedMessage::Legacy(LegacyMessage::new(message))
+            }
+            SanitizedMessage::V0(loaded) => {
+                let mut message = loaded.message.clone().into_owned();
+                let v0::Message {
+                    header,
+                    account_keys,
+                    instructions,
+                    ..
+                } = &mut message;
+                for signer in signers {
+                    if loaded.loaded_addresses.writable.contains(signer)
+                        || loaded.loaded_addresses.readonly.contains(signer)
+                    {
+                        throw!(MessageExecutorError::LookedUpSigner(*signer));
+                    }
+                    move_to_signers(header, account_keys, instructions, signer);
+                }
+                let loaded_addresses = loaded.loaded_addresses.clone().into_owned();
+                SanitizedMessage::V0(LoadedMessage::new(message, loaded_addresses))
+            }
+        }
+    }
+
+    // The epoch schedule sysvar if set, otherwise the default epoch schedule
+    pub fn epoch_schedule(&self) -> EpochSchedule {
+        self.sysvar_cache
+            .get_epoch_schedule()
+            .map(|epoch_schedule| *epoch_schedule)
+            .unwrap_or_default()
+    }
+
+    pub fn working_slot(&self, slot: Slot) -> WorkingSlot {
+        WorkingSlot::new(slot, self.epoch_schedule())
+    }
+
+    pub fn fork_graph(&self) -> ForkGraph {
+        ForkGraph::new(self.epoch_schedule())
+    }
+
+    // The rent sysvar if set, otherwise the default rent
+    pub fn rent(&self) -> Rent {
+        self.sysvar_cache
+            .get_rent()
+            .map(|rent| *rent)
+            .unwrap_or_default()
+    }
+
+    // A rent collector for the epoch of the clock sysvar, see Bank::rent_collector
+    pub fn rent_collector(&self) -> RentCollector {
+        let mut rent_collector = RentCollector {
+            rent: self.rent(),
+            epoch_schedule: self.epoch_schedule(),
+            ..RentCollector::default()
+        };
+        if let Ok(clock) = self.sysvar_cache.get_clock() {
+            rent_collector.epoch = clock.epoch;
+        }
+        rent_collector
+    }
+
+    // Roughly the compute budget part of Bank::load_and_execute_transactions
+    #[throws(TransactionError)]
+    pub fn compute_budget(&self, message: &SanitizedMessage) -> ComputeBudget {
+        let mut compute_budget = ComputeBudget::new(compute_budget::MAX_COMPUTE_UNIT_LIMIT as u64);
+        compute_budget.process_instructions(
+            message.program_instructions_iter(),
+            !self
+                .feature_set
+                .is_active(&remove_deprecated_request_unit_ix::id()),
+            self.feature_set
+                .is_active(&add_set_tx_loaded_accounts_data_size_instruction::id()),
+        )?;
+        compute_budget
+    }
+
+    // Bank::get_fee_for_message_with_lamports_per_signature
+    pub fn calculate_fee(&self, message: &SanitizedMessage) -> u64 {
+        self.fee_structure.calculate_fee(
+            message,
+            self.fee_structure.lamports_per_signature,
+            &ComputeBudget::fee_budget_limits(
+                message.program_instructions_iter(),
+                &self.feature_set,
+            ),
+            self.feature_set
+                .is_active(&remove_congestion_multiplier_from_fee_calculation::id()),
+            self.feature_set
+                .is_active(&include_loaded_accounts_data_size_in_fee_calculation::id()),
+        )
+    }
+
+    // Roughly Accounts::validate_fee_payer
+    #[throws(TransactionError)]
+    fn charge_fee_payer(
+        &self,
+        rent: &Rent,
+        message: &SanitizedMessage,
+        accounts: &mut [(Pubkey, AccountSharedData)],
+        fee: u64,
+    ) {
+        // Unlike the bank, allow unsigned messages to be simulated as long as nothing is owed.
+        if message.header().num_required_signatures == 0 {
+            if fee != 0 {
+                throw!(TransactionError::MissingSignatureForFee);
+            }
+            return;
+        }
+
+        let (payer_index, (payer_address, payer_account)) = accounts
+            .iter_mut()
+            .enumerate()
+            .find(|(_, (key, _))| key == message.fee_payer())
+            .ok_or(TransactionError::AccountNotFound)?;
+
+        if payer_account.lamports() == 0 {
+            throw!(TransactionError::AccountNotFound);
+        }
+
+        let min_balance = match get_system_account_kind(payer_account)
+            .ok_or(TransactionError::InvalidAccountForFee)?
+        {
+            SystemAccountKind::System => 0,
+            SystemAccountKind::Nonce => rent.minimum_balance(nonce::State::size()),
+        };
+
+        if self
+            .feature_set
+            .is_active(&checked_arithmetic_in_fee_validation::id())
+        {
+            payer_account
+                .lamports()
+                .checked_sub(min_balance)
+                .and_then(|v| v.checked_sub(fee))
+                .ok_or(TransactionError::InsufficientFundsForFee)?;
+        } else if payer_account.lamports() < fee.saturating_add(min_balance) {
+            throw!(TransactionError::InsufficientFundsForFee);
+        }
+
+        let pre_payer_account = payer_account.clone();
+        payer_account
+            .checked_sub_lamports(fee)
+            .map_err(|_| TransactionError::InsufficientFundsForFee)?;
+
+        Self::check_fee_payer_rent_state(
+            rent,
+            payer_address,
+            &pre_payer_account,
+            payer_account,
+            payer_index as IndexOfAccount,
+        )?;
+    }
+
+    // process_with_options with the default options, capturing logs like the runtime
     #[throws(Error)]
     pub fn process(
         &self,
@@ -133,45 +444,560 @@ impl MessageExecutor {
         loaded_transaction: LoadedTransaction,
         loaded_programs: &LoadedProgramsForTxBatch,
     ) -> ExecutionRecord {
-        let compute_budget = ComputeBudget::default();
-        let mut transaction_context =
-            TransactionContext::new(loaded_transaction.accounts, None, 10, usize::MAX);
+        self.process_with_options(
+            slot,
+            message,
+            loaded_transaction,
+            loaded_programs,
+            &ProcessOptions::default(),
+        )?
+    }
+
+    #[throws(Error)]
+    pub fn process_with_options(
+        &self,
+        slot: Slot,
+        message: &SanitizedMessage,
+        loaded_transaction: LoadedTransaction,
+        loaded_programs: &LoadedProgramsForTxBatch,
+        options: &ProcessOptions,
+    ) -> ExecutionRecord {
+        let LoadedTransaction {
+            mut accounts,
+            program_indices,
+            rent: tx_rent,
+            rent_debits,
+        } = loaded_transaction;
 
-        let mut units = 0;
+        let compute_budget = self.compute_budget(message)?;
+        let rent = self.rent();
+        let tombstones = self.tombstones(&accounts, loaded_programs);
+
+        // The accounts as the source served them, rent was collected while loading
+        let pre_datas = accounts
+            .iter()
+            .take(message.account_keys().len())
+            .map(|(key, account)| {
+                let mut account = account.clone();
+                account.set_lamports(account.lamports() + rent_debits.get_account_rent_debit(key));
+                account
+            })
+            .collect();
+
+        let fee = self.calculate_fee(message);
+        self.charge_fee_payer(&rent, message, &mut accounts, fee)?;
+
+        let mut transaction_context = TransactionContext::new(
+            accounts,
+            self.feature_set
+                .is_active(&enable_early_verification_of_account_modifications::id())
+                .then_some(rent),
+            compute_budget.max_invoke_stack_height,
+            usize::MAX,
+        );
+        let pre_account_state_info =
+            Self::get_transaction_account_state_info(&rent, &transaction_context, message);
+
+        let mut instruction_units = vec![];
         let mut timing = ExecuteTimings::default();
 
-        let mut p1 = LoadedProgramsForTxBatch::new(slot, self.loaded_programs.environments.clone());
-        let mut p2 = LoadedProgramsForTxBatch::new(slot, self.loaded_programs.environments.clone());
-        MessageProcessor::process_message(
+        let mut programs_modified_by_tx =
+            LoadedProgramsForTxBatch::new(slot, self.loaded_programs.environments.clone());
+        let mut programs_updated_only_for_global_cache =
+            LoadedProgramsForTxBatch::new(slot, self.loaded_programs.environments.clone());
+        // Each call logs into its own collector so that concurrent calls do not interleave, the
+        // limit is applied afterwards, see ProcessOptions::apply
+        let logger = options
+            .capture_logs
+            .then(|| LogCollector::new_ref_with_limit(None));
+        let mock_scope = MockScope::enter(&self.mock_programs);
+        let status = process_message(
             message,
-            &loaded_transaction.program_indices,
+            &program_indices,
             &mut transaction_context,
-            Rent::default(),
-            self.logger.clone(),
+            rent,
+            logger.clone(),
             loaded_programs,
-            &mut p1,
-            &mut p2,
+            &mut programs_modified_by_tx,
+            &mut programs_updated_only_for_global_cache,
             self.feature_set.clone(),
             compute_budget,
             &mut timing,
             &self.sysvar_cache,
             *message.recent_blockhash(),
+            self.fee_structure.lamports_per_signature,
             0,
-            0,
-            &mut units,
-        )?;
+            &mut instruction_units,
+        )
+        .and_then(|_| {
+            let post_account_state_info =
+                Self::get_transaction_account_state_info(&rent, &transaction_context, message);
+            Self::verify_transaction_account_state_changes(
+                &pre_account_state_info,
+                &post_account_state_info,
+                &transaction_context,
+            )
+        });
+
+        drop(mock_scope);
+
+        // Modifications made by a failed transaction are dropped, like in Bank::commit_transactions
+        if status.is_ok() {
+            programs_updated_only_for_global_cache.merge(&programs_modified_by_tx);
+        }
+
+        let logs = match logger {
+            Some(logger) => logger.take().into_messages(),
+            None => vec![],
+        };
+        let inner_instructions =
+            inner_instructions(&transaction_context, message, &instruction_units, &logs);
+        let mut logs = logs_by_instruction(&logs);
+        options.apply(&mut logs);
+
+        let return_data = transaction_context.get_return_data();
+        let return_data = (!return_data.1.is_empty()).then(|| TransactionReturnData {
+            program_id: *return_data.0,
+            data: return_data.1.to_vec(),
+        });
 
         let keys = message.account_keys().iter().copied().collect();
         let datas: Vec<_> = transaction_context.deconstruct_without_keys()?;
 
         ExecutionRecord {
             keys,
+            pre_datas,
             datas,
-            cu: units,
+            cu: instruction_units.iter().flatten().sum(),
+            fee,
+            rent: tx_rent,
+            rent_debits,
+            tombstones,
+            programs_modified: programs_updated_only_for_global_cache,
+            inner_instructions,
+            return_data,
+            logs,
+            status,
+        }
+    }
+
+    pub fn program_load_error(&self, program_id: &Pubkey) -> Option<&ProgramLoadError> {
+        self.program_load_errors.get(program_id)
+    }
+
+    // The executable accounts of a transaction that resolved to tombstones, and why
+    fn tombstones(
+        &self,
+        accounts: &[(Pubkey, AccountSharedData)],
+        loaded_programs: &LoadedProgramsForTxBatch,
+    ) -> Vec<(Pubkey, ProgramLoadError)> {
+        accounts
+            .iter()
+            .filter(|(_, account)| account.executable() || loader_v4::check_id(account.owner()))
+            .filter_map(|(key, _)| {
+                let program = loaded_programs.find(key)?;
+                let reason = match &program.program {
+                    LoadedProgramType::Closed => ProgramLoadError::Closed,
+                    LoadedProgramType::DelayVisibility => ProgramLoadError::DelayVisibility,
+                    LoadedProgramType::FailedVerification(_) => self
+                        .program_load_errors
+                        .get(key)
+                        .cloned()
+                        .unwrap_or(ProgramLoadError::FailedVerification),
+                    _ => return None,
+                };
+                Some((*key, reason))
+            })
+            .collect()
+    }
+}
+
+// Marks the static account `signer` as a signer by moving it to the end of the signed accounts with
+// the same writability, and shifts the instruction indices of the accounts it passed
+fn move_to_signers(
+    header: &mut MessageHeader,
+    account_keys: &mut Vec<Pubkey>,
+    instructions: &mut [CompiledInstruction],
+    signer: &Pubkey,
+) {
+    let num_signed = header.num_required_signatures as usize;
+    let Some(index) = account_keys.iter().position(|key| key == signer) else {
+        return;
+    };
+    if index < num_signed {
+        return;
+    }
+
+    let writable = index < account_keys.len() - header.num_readonly_unsigned_accounts as usize;
+    let target = if writable {
+        num_signed - header.num_readonly_signed_accounts as usize
+    } else {
+        num_signed
+    };
+    let key = account_keys.remove(index);
+    account_keys.insert(target, key);
+
+    header.num_required_signatures += 1;
+    if !writable {
+        header.num_readonly_signed_accounts += 1;
+        header.num_readonly_unsigned_accounts -= 1;
+    }
+
+    // Loaded addresses come after the static accounts and keep their indices
+    let remap = |i: &mut u8| {
+        let old = *i as usize;
+        if old == index {
+            *i = target as u8;
+        } else if (target..index).contains(&old) {
+            *i += 1;
+        }
+    };
+    for instruction in instructions {
+        remap(&mut instruction.program_id_index);
+        instruction.accounts.iter_mut().for_each(remap);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, thread};
+
+    use solana_sdk::{
+        account::{AccountSharedData, ReadableAccount},
+        compute_budget::ComputeBudgetInstruction,
+        ed25519_program,
+        epoch_schedule::EpochSchedule,
+        hash::Hash,
+        instruction::{AccountMeta, CompiledInstruction, Instruction},
+        message::{
+            v0::{self, LoadedAddresses, LoadedMessage},
+            LegacyMessage, Message, MessageHeader, SanitizedMessage, SimpleAddressLoader,
+            VersionedMessage,
+        },
+        nonce::{self, state::DurableNonce},
+        pubkey::Pubkey,
+        rent::Rent,
+        signature::{Keypair, Signature, Signer},
+        system_instruction, system_program,
+        transaction::{TransactionError, VersionedTransaction},
+    };
+
+    use super::super::{
+        system_account,
+        test_utils::{executor, message, run, source, NOOP_ELF, SLOT},
+        upgradeable_program_accounts, MessageExecutorError,
+    };
+
+    #[test]
+    fn fee_includes_the_prioritization_fee() {
+        let mut sbf = executor();
+        let payer = Pubkey::new_unique();
+        let accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        let message = message(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(10_000),
+                ComputeBudgetInstruction::set_compute_unit_price(1_000_000),
+            ],
+            &payer,
+        );
+
+        // 5000 for the signature, 1 lamport per CU for the 10k requested CUs
+        assert_eq!(sbf.calculate_fee(&message), 15_000);
+        let record = run(&mut sbf, &message, &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+        assert_eq!(record.fee, 15_000);
+        assert_eq!(record.datas[0].lamports(), 1_000_000_000 - 15_000);
+    }
+
+    #[test]
+    fn fee_payer_without_enough_lamports_fails() {
+        let mut sbf = executor();
+        let payer = Pubkey::new_unique();
+        let accounts = HashMap::from([(payer, system_account(4_999))]);
+        let message = message(
+            &[ComputeBudgetInstruction::set_compute_unit_limit(10_000)],
+            &payer,
+        );
+
+        let e = run(&mut sbf, &message, &accounts).unwrap_err();
+        assert_eq!(
+            e.downcast::<TransactionError>().unwrap(),
+            TransactionError::InsufficientFundsForFee
+        );
+    }
+
+    #[test]
+    fn nonce_fee_payer_keeps_its_minimum_balance() {
+        let nonce_account = |lamports| {
+            let state = nonce::State::Initialized(nonce::state::Data::new(
+                Pubkey::new_unique(),
+                DurableNonce::from_blockhash(&Hash::default()),
+                5_000,
+            ));
+            AccountSharedData::new_data_with_space(
+                lamports,
+                &nonce::state::Versions::new(state),
+                nonce::State::size(),
+                &system_program::id(),
+            )
+            .unwrap()
+        };
+        let mut sbf = executor();
+        let payer = Pubkey::new_unique();
+        let message = message(
+            &[ComputeBudgetInstruction::set_compute_unit_limit(10_000)],
+            &payer,
+        );
+        let minimum_balance = Rent::default().minimum_balance(nonce::State::size());
+
+        let accounts = HashMap::from([(payer, nonce_account(minimum_balance + 4_999))]);
+        let e = run(&mut sbf, &message, &accounts).unwrap_err();
+        assert_eq!(
+            e.downcast::<TransactionError>().unwrap(),
+            TransactionError::InsufficientFundsForFee
+        );
+
+        let accounts = HashMap::from([(payer, nonce_account(minimum_balance + 5_000))]);
+        let record = run(&mut sbf, &message, &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+        assert_eq!(record.datas[0].lamports(), minimum_balance);
+    }
+
+    #[test]
+    fn program_cache_follows_a_custom_epoch_schedule() {
+        let mut sbf = executor();
+        let epoch_schedule = EpochSchedule::custom(64, 64, false);
+        sbf.sysvar_cache_mut().set_epoch_schedule(epoch_schedule);
+        sbf.set_synthetic_sysvars(SLOT, 0);
+
+        let program_id = Pubkey::new_unique();
+        let payer = Pubkey::new_unique();
+        let mut accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        accounts.extend(
+            upgradeable_program_accounts(&program_id, NOOP_ELF, None, 0, &Rent::default()).unwrap(),
+        );
+        let ix = Instruction::new_with_bytes(program_id, &[], vec![]);
+        let record = run(&mut sbf, &message(&[ix], &payer), &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+
+        let root_slot = sbf.loaded_programs.latest_root_slot;
+        assert_eq!(
+            sbf.loaded_programs.latest_root_epoch,
+            epoch_schedule.get_epoch(root_slot)
+        );
+        assert_ne!(
+            sbf.loaded_programs.latest_root_epoch,
+            EpochSchedule::default().get_epoch(root_slot)
+        );
+        assert_eq!(sbf.sysvar_cache().get_clock().unwrap().epoch, SLOT / 64);
+    }
+
+    #[test]
+    fn shared_executor_matches_sequential_runs() {
+        let mut sbf = executor();
+        let mock = Pubkey::new_unique();
+        sbf.add_mock_program(mock, |invocation| {
+            invocation.logs.push(format!("seed {}", invocation.data[0]));
+            invocation.return_data = Some(invocation.data.repeat(2));
+            Ok(())
+        });
+
+        let payer = Pubkey::new_unique();
+        let accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        let messages: Vec<_> = (0..8u8)
+            .map(|i| {
+                let transfer =
+                    system_instruction::transfer(&payer, &Pubkey::new_unique(), 1_000_000);
+                let call =
+                    Instruction::new_with_bytes(mock, &[i], vec![AccountMeta::new(payer, true)]);
+                message(&[transfer, call], &payer)
+            })
+            .collect();
+
+        let sequential: Vec<_> = messages
+            .iter()
+            .map(|message| run(&mut sbf, message, &accounts).unwrap())
+            .collect();
+
+        let working_slot = sbf.working_slot(SLOT);
+        let mut loader = sbf.loader(source(&accounts));
+        let loaded: Vec<_> = messages
+            .iter()
+            .map(|message| loader.load(&working_slot, message).unwrap())
+            .collect();
+        let sbf = &sbf;
+        let concurrent: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = messages
+                .iter()
+                .zip(loaded)
+                .map(|(message, (loaded_transaction, loaded_programs))| {
+                    s.spawn(move || {
+                        sbf.process(SLOT, message, loaded_transaction, &loaded_programs)
+                            .unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        for (i, (expected, record)) in sequential.iter().zip(&concurrent).enumerate() {
+            assert_eq!(record.status, Ok(()));
+            assert_eq!(record.return_data.as_ref().unwrap().data, vec![i as u8; 2]);
+            assert_eq!(record.status, expected.status);
+            assert_eq!(record.keys, expected.keys);
+            assert_eq!(record.datas, expected.datas);
+            assert_eq!(record.cu, expected.cu);
+            assert_eq!(record.fee, expected.fee);
+            assert_eq!(record.logs, expected.logs);
+            assert_eq!(record.return_data, expected.return_data);
         }
     }
 
-    // pub fn prune<F: ForkGraph>(&mut self, fork_graph: &F, new_root: Slot) {
-    //     self.loaded_programs.prune(fork_graph, new_root);
-    // }
+    #[test]
+    fn sanitize_transaction_verifies_signatures_and_precompiles() {
+        let mut sbf = executor();
+        let payer = Keypair::new();
+        let sign = |instructions: &[Instruction]| {
+            let message = Message::new(instructions, Some(&payer.pubkey()));
+            VersionedTransaction::try_new(VersionedMessage::Legacy(message), &[&payer]).unwrap()
+        };
+        let transfer = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+        // Claims one signature without the offsets that should follow
+        let precompile = Instruction::new_with_bytes(ed25519_program::id(), &[1, 0], vec![]);
+
+        let invalid_precompile = sign(&[precompile, transfer.clone()]);
+        let tx = sign(&[transfer]);
+        let mut forged = tx.clone();
+        forged.signatures[0] = Signature::new_unique();
+
+        for tx in [&tx, &forged, &invalid_precompile] {
+            assert!(sbf
+                .sanitize_transaction(tx.clone(), SimpleAddressLoader::Disabled)
+                .is_ok());
+        }
+
+        sbf.set_sig_verify(true);
+        assert!(sbf
+            .sanitize_transaction(tx, SimpleAddressLoader::Disabled)
+            .is_ok());
+        assert_eq!(
+            sbf.sanitize_transaction(forged, SimpleAddressLoader::Disabled)
+                .unwrap_err(),
+            TransactionError::SignatureFailure
+        );
+        assert_eq!(
+            sbf.sanitize_transaction(invalid_precompile, SimpleAddressLoader::Disabled)
+                .unwrap_err(),
+            TransactionError::InvalidAccountIndex
+        );
+    }
+
+    #[test]
+    fn mark_signers_moves_only_the_signers() {
+        let mut sbf = executor();
+        let [payer, writable, unused, readonly, program] = [(); 5].map(|_| Pubkey::new_unique());
+        let message = Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 2,
+            },
+            account_keys: vec![payer, writable, unused, readonly, program],
+            recent_blockhash: Hash::new_unique(),
+            instructions: vec![CompiledInstruction::new_from_raw_parts(
+                4,
+                vec![7],
+                vec![3, 0, 1],
+            )],
+        };
+        let message = SanitizedMessage::Legacy(LegacyMessage::new(message));
+
+        let marked = sbf.mark_signers(&message, &[readonly, writable]).unwrap();
+        let keys: Vec<_> = marked.account_keys().iter().copied().collect();
+        // The account no instruction refers to is kept in place
+        assert_eq!(keys, vec![payer, writable, readonly, unused, program]);
+        assert_eq!(
+            *marked.header(),
+            MessageHeader {
+                num_required_signatures: 3,
+                num_readonly_signed_accounts: 1,
+                num_readonly_unsigned_accounts: 1,
+            }
+        );
+        assert!(marked.is_signer(1) && marked.is_writable(1));
+        assert!(marked.is_signer(2) && !marked.is_writable(2));
+        assert!(!marked.is_signer(3) && marked.is_writable(3));
+        assert_eq!(marked.fee_payer(), &payer);
+        assert_eq!(marked.recent_blockhash(), message.recent_blockhash());
+
+        let instruction = &marked.decompile_instructions()[0];
+        assert_eq!(instruction.program_id, &program);
+        assert_eq!(instruction.data, &[7]);
+        let metas: Vec<_> = instruction
+            .accounts
+            .iter()
+            .map(|meta| (*meta.pubkey, meta.is_signer, meta.is_writable))
+            .collect();
+        assert_eq!(
+            metas,
+            vec![
+                (readonly, true, false),
+                (payer, true, true),
+                (writable, true, true)
+            ]
+        );
+
+        sbf.set_sig_verify(true);
+        let e = sbf.mark_signers(&message, &[readonly]).unwrap_err();
+        assert!(matches!(
+            e.downcast::<MessageExecutorError>().unwrap(),
+            MessageExecutorError::SigVerifyEnabled
+        ));
+    }
+
+    #[test]
+    fn mark_signers_keeps_address_table_lookups() {
+        let sbf = executor();
+        let [payer, readonly, program, looked_up] = [(); 4].map(|_| Pubkey::new_unique());
+        let message = v0::Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 2,
+            },
+            account_keys: vec![payer, readonly, program],
+            recent_blockhash: Hash::new_unique(),
+            instructions: vec![CompiledInstruction::new_from_raw_parts(
+                2,
+                vec![],
+                vec![1, 3],
+            )],
+            address_table_lookups: vec![v0::MessageAddressTableLookup {
+                account_key: Pubkey::new_unique(),
+                writable_indexes: vec![0],
+                readonly_indexes: vec![],
+            }],
+        };
+        let loaded_addresses = LoadedAddresses {
+            writable: vec![looked_up],
+            readonly: vec![],
+        };
+        let message = SanitizedMessage::V0(LoadedMessage::new(message, loaded_addresses));
+
+        let marked = sbf.mark_signers(&message, &[readonly]).unwrap();
+        let SanitizedMessage::V0(loaded) = &marked else {
+            panic!("expected a v0 message");
+        };
+        assert_eq!(loaded.message.account_keys, vec![payer, readonly, program]);
+        assert_eq!(loaded.message.instructions[0].accounts, vec![1, 3]);
+        assert_eq!(loaded.loaded_addresses.writable, vec![looked_up]);
+        assert!(marked.is_signer(1) && !marked.is_writable(1));
+        assert!(marked.is_writable(3));
+
+        let e = sbf.mark_signers(&message, &[looked_up]).unwrap_err();
+        assert!(matches!(
+            e.downcast::<MessageExecutorError>().unwrap(),
+            MessageExecutorError::LookedUpSigner(signer) if signer == looked_up
+        ));
+    }
 }
@@ -0,0 +1,83 @@
+use solana_sdk::{
+    account::AccountSharedData,
+    feature,
+    feature_set::{FeatureSet, FEATURE_NAMES},
+    pubkey::Pubkey,
+    slot_history::Slot,
+};
+
+// Roughly Bank::compute_active_feature_set, reading every known feature account through `g`.
+// Features activated after `slot` or still pending are left inactive.
+pub fn feature_set_from_accounts<G>(slot: Slot, mut g: G) -> FeatureSet
+where
+    G: FnMut(&Pubkey) -> Option<AccountSharedData>,
+{
+    let mut feature_set = FeatureSet::default();
+
+    for feature_id in FEATURE_NAMES.keys() {
+        let activated_at = g(feature_id)
+            .and_then(|account| feature::from_account(&account))
+            .and_then(|feature| feature.activated_at);
+
+        if let Some(activation_slot) =
+            activated_at.filter(|&activation_slot| slot >= activation_slot)
+        {
+            feature_set.activate(feature_id, activation_slot);
+        }
+    }
+
+    feature_set
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_sdk::{
+        account::{AccountSharedData, WritableAccount},
+        feature::{self, Feature},
+        feature_set::{
+            blake3_syscall_enabled, curve25519_syscall_enabled, disable_fees_sysvar,
+            enable_partitioned_epoch_reward, native_programs_consume_cu,
+        },
+        system_program,
+    };
+
+    use super::feature_set_from_accounts;
+
+    #[test]
+    fn features_activated_up_to_the_slot() {
+        let feature = |activated_at| feature::create_account(&Feature { activated_at }, 1);
+        let mut not_a_feature = feature(Some(10));
+        not_a_feature.set_owner(system_program::id());
+        let accounts = HashMap::from([
+            (blake3_syscall_enabled::id(), feature(Some(10))),
+            (curve25519_syscall_enabled::id(), feature(Some(100))),
+            (disable_fees_sysvar::id(), feature(Some(101))), // after the slot
+            (enable_partitioned_epoch_reward::id(), feature(None)), // pending
+            (native_programs_consume_cu::id(), not_a_feature),
+        ]);
+
+        let feature_set = feature_set_from_accounts(100, |pubkey| accounts.get(pubkey).cloned());
+        assert_eq!(feature_set.active.len(), 2);
+        assert_eq!(
+            feature_set.activated_slot(&blake3_syscall_enabled::id()),
+            Some(10)
+        );
+        assert_eq!(
+            feature_set.activated_slot(&curve25519_syscall_enabled::id()),
+            Some(100)
+        );
+        for feature_id in [
+            disable_fees_sysvar::id(),
+            enable_partitioned_epoch_reward::id(),
+            native_programs_consume_cu::id(),
+        ] {
+            assert!(!feature_set.is_active(&feature_id));
+            assert!(feature_set.inactive.contains(&feature_id));
+        }
+
+        let feature_set = feature_set_from_accounts(100, |_| Option::<AccountSharedData>::None);
+        assert!(feature_set.active.is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+use std::{iter::Peekable, str::FromStr};
+
+use solana_accounts_db::transaction_results::inner_instructions_list_from_instruction_trace;
+use solana_sdk::{
+    instruction::{CompiledInstruction, TRANSACTION_LEVEL_STACK_HEIGHT},
+    message::SanitizedMessage,
+    pubkey::Pubkey,
+    transaction_context::TransactionContext,
+};
+
+// The CPIs of a top-level instruction, shaped like the `innerInstructions` of RPC
+#[derive(Debug, Clone)]
+pub struct InnerInstructions {
+    pub index: u8,                           // index of the top-level instruction
+    pub compute_units_consumed: Option<u64>, // by the top-level invocation including its CPIs, None for precompiles
+    pub instructions: Vec<InnerInstruction>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InnerInstruction {
+    pub instruction: CompiledInstruction, // indices into the keys of the ExecutionRecord
+    pub stack_height: u8,
+    pub compute_units_consumed: Option<u64>, // None for builtins or when the logs are truncated
+}
+
+// An invocation found in the program logs
+struct Invocation {
+    program_id: Pubkey,
+    stack_height: u8,
+    compute_units_consumed: Option<u64>,
+}
+
+// Bank::execute_loaded_transaction records the instruction trace. The compute units of the top-level
+// instructions come from the runtime, see process_message, those of CPIs are only found in the logs.
+pub(crate) fn inner_instructions(
+    transaction_context: &TransactionContext,
+    message: &SanitizedMessage,
+    instruction_units: &[Option<u64>],
+    logs: &[String],
+) -> Vec<InnerInstructions> {
+    let keys: Vec<_> = message.account_keys().iter().copied().collect();
+    let mut invocations = invocations_from_logs(logs).into_iter().peekable();
+
+    inner_instructions_list_from_instruction_trace(transaction_context)
+        .into_iter()
+        .enumerate()
+        .map(|(index, inner_instructions)| {
+            let program_id = message
+                .instructions()
+                .get(index)
+                .and_then(|ix| keys.get(ix.program_id_index as usize));
+
+            // Skip the logged top-level invocation so that the CPIs line up
+            next_compute_units(
+                &mut invocations,
+                program_id,
+                TRANSACTION_LEVEL_STACK_HEIGHT as u8,
+            );
+
+            InnerInstructions {
+                index: index as u8,
+                compute_units_consumed: instruction_units.get(index).copied().flatten(),
+                instructions: inner_instructions
+                    .into_iter()
+                    .map(|inner| InnerInstruction {
+                        compute_units_consumed: next_compute_units(
+                            &mut invocations,
+                            keys.get(inner.instruction.program_id_index as usize),
+                            inner.stack_height,
+                        ),
+                        instruction: inner.instruction,
+                        stack_height: inner.stack_height,
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+// Consume the next logged invocation if it belongs to the given program and stack height
+fn next_compute_units<I: Iterator<Item = Invocation>>(
+    invocations: &mut Peekable<I>,
+    program_id: Option<&Pubkey>,
+    stack_height: u8,
+) -> Option<u64> {
+    invocations
+        .next_if(|invocation| {
+            Some(&invocation.program_id) == program_id && invocation.stack_height == stack_height
+        })
+        .and_then(|invocation| invocation.compute_units_consumed)
+}
+
+// Start a new group at every top-level invocation
+pub(crate) fn logs_by_instruction(logs: &[String]) -> Vec<Vec<String>> {
+    let mut groups: Vec<Vec<String>> = vec![];
+    for log in logs {
+        let top_level = matches!(
+            log.split_whitespace().collect::<Vec<_>>().as_slice(),
+            ["Program", _, "invoke", "[1]"]
+        );
+        match groups.last_mut() {
+            Some(group) if !top_level => group.push(log.clone()),
+            _ => groups.push(vec![log.clone()]),
+        }
+    }
+    groups
+}
+
+// Follows the "invoke", "consumed" and "success"/"failed" messages of stable_log
+fn invocations_from_logs(logs: &[String]) -> Vec<Invocation> {
+    let mut invocations: Vec<Invocation> = vec![];
+    let mut stack: Vec<usize> = vec![];
+
+    for log in logs {
+        let tokens: Vec<_> = log.split_whitespace().collect();
+        let program_id = match tokens.as_slice() {
+            ["Program", program_id, ..] => match Pubkey::from_str(program_id) {
+                Ok(program_id) => program_id,
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+
+        match tokens.as_slice() {
+            [_, _, "invoke", height] => {
+                let stack_height = height
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse()
+                    .unwrap_or(u8::MAX);
+                stack.push(invocations.len());
+                invocations.push(Invocation {
+                    program_id,
+                    stack_height,
+                    compute_units_consumed: None,
+                });
+            }
+            [_, _, "consumed", units, "of", _, "compute", "units"] => {
+                if let Some(invocation) = stack.last().and_then(|&i| invocations.get_mut(i)) {
+                    invocation.compute_units_consumed = units.parse().ok();
+                }
+            }
+            [_, _, "success"] | [_, _, "failed:", ..] => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    invocations
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_sdk::{
+        address_lookup_table::instruction::create_lookup_table_signed,
+        feature_set::native_programs_consume_cu, pubkey::Pubkey, system_instruction,
+        system_program,
+    };
+
+    use super::{
+        super::{
+            system_account,
+            test_utils::{feature_set, message, run, SLOT},
+            MessageExecutor,
+        },
+        invocations_from_logs, logs_by_instruction,
+    };
+
+    fn invocations(logs: &[String]) -> Vec<(Pubkey, u8, Option<u64>)> {
+        invocations_from_logs(logs)
+            .into_iter()
+            .map(|i| (i.program_id, i.stack_height, i.compute_units_consumed))
+            .collect()
+    }
+
+    #[test]
+    fn nested_invocations() {
+        let (a, b, c) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let logs = vec![
+            format!("Program {a} invoke [1]"),
+            "Program log: before the CPI".to_string(),
+            format!("Program {b} invoke [2]"),
+            format!("Program {b} consumed 100 of 1000 compute units"),
+            format!("Program {b} success"),
+            format!("Program {a} consumed 300 of 1300 compute units"),
+            format!("Program {a} success"),
+            format!("Program {c} invoke [1]"),
+            format!("Program {c} success"),
+        ];
+
+        assert_eq!(
+            invocations(&logs),
+            vec![(a, 1, Some(300)), (b, 2, Some(100)), (c, 1, None)]
+        );
+        assert_eq!(
+            logs_by_instruction(&logs),
+            vec![logs[..7].to_vec(), logs[7..].to_vec()]
+        );
+    }
+
+    #[test]
+    fn failed_invocation() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let logs = vec![
+            format!("Program {a} invoke [1]"),
+            format!("Program {b} invoke [2]"),
+            format!("Program {b} consumed 50 of 900 compute units"),
+            format!("Program {b} failed: custom program error: 0x1"),
+            format!("Program {a} consumed 200 of 1000 compute units"),
+            format!("Program {a} failed: custom program error: 0x1"),
+        ];
+
+        assert_eq!(
+            invocations(&logs),
+            vec![(a, 1, Some(200)), (b, 2, Some(50))]
+        );
+        assert_eq!(logs_by_instruction(&logs), vec![logs.clone()]);
+    }
+
+    #[test]
+    fn truncated_logs() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let logs = vec![
+            format!("Program {a} invoke [1]"),
+            format!("Program {b} invoke [2]"),
+            "Log truncated".to_string(),
+        ];
+
+        // Compute units that were not logged are unknown rather than attributed to another invocation
+        assert_eq!(invocations(&logs), vec![(a, 1, None), (b, 2, None)]);
+        assert_eq!(logs_by_instruction(&logs), vec![logs.clone()]);
+        assert!(logs_by_instruction(&[]).is_empty());
+    }
+
+    #[test]
+    fn compute_units_of_a_cpi() {
+        let mut features = feature_set();
+        features.activate(&native_programs_consume_cu::id(), 0);
+        let mut sbf = MessageExecutor::new_with_feature_set(features).unwrap();
+        sbf.set_synthetic_sysvars(SLOT, 0);
+        let (payer, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+
+        // The lookup table program creates the table through CPIs to the system program
+        let (create, table) = create_lookup_table_signed(payer, payer, SLOT - 1);
+        let transfer = system_instruction::transfer(&payer, &recipient, 1_000_000);
+        let record = run(&mut sbf, &message(&[create, transfer], &payer), &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+
+        // Builtins do not log their compute units, the top-level ones come from the runtime
+        let units: Vec<_> = record
+            .inner_instructions
+            .iter()
+            .map(|inner| (inner.index, inner.compute_units_consumed))
+            .collect();
+        assert_eq!(units, vec![(0, Some(1_200)), (1, Some(150))]);
+        assert_eq!(record.cu, 1_350);
+
+        let system_program = record
+            .keys
+            .iter()
+            .position(|key| *key == system_program::id())
+            .unwrap();
+        let cpis: Vec<_> = record.inner_instructions[0]
+            .instructions
+            .iter()
+            .map(|inner| {
+                (
+                    inner.instruction.program_id_index as usize,
+                    record.keys[inner.instruction.accounts[0] as usize],
+                    inner.stack_height,
+                    inner.compute_units_consumed,
+                )
+            })
+            .collect();
+        assert_eq!(
+            cpis,
+            vec![
+                (system_program, payer, 2, None), // transfer
+                (system_program, table, 2, None), // allocate
+                (system_program, table, 2, None), // assign
+            ]
+        );
+        assert!(record.inner_instructions[1].instructions.is_empty());
+    }
+}
@@ -1,13 +1,21 @@
 mod program_loader;
+mod source;
 mod transaction_loader;
 
+pub use source::{AccountSource, RetryPolicy, RpcAccountSource};
+
 use anyhow::Error;
 use fehler::throws;
-use solana_program_runtime::loaded_programs::LoadedPrograms;
-use solana_sdk::{account::AccountSharedData, feature_set::FeatureSet, pubkey::Pubkey};
-use std::collections::HashSet;
+use solana_accounts_db::{accounts::LoadedTransaction, rent_collector::RentCollector};
+use solana_program_runtime::loaded_programs::{
+    LoadedPrograms, LoadedProgramsForTxBatch, WorkingSlot,
+};
+use solana_sdk::{
+    account::AccountSharedData, feature_set::FeatureSet, message::SanitizedMessage, pubkey::Pubkey,
+};
+use std::collections::{HashMap, HashSet};
 
-use super::{ForkGraph, MessageExecutor};
+use super::{AccountLoadError, ForkGraph, MessageExecutor, ProgramLoadError, ProgramUses};
 
 pub struct AccountLoader<'a, G> {
     g: G,
@@ -15,15 +23,24 @@ pub struct AccountLoader<'a, G> {
     loaded_programs_cache: &'a mut LoadedPrograms<ForkGraph>,
     program_owners: &'a HashSet<Pubkey>,
     builtin_programs: &'a HashSet<Pubkey>,
+    program_load_errors: &'a mut HashMap<Pubkey, ProgramLoadError>,
+    program_uses: &'a mut ProgramUses,
+    rent_collector: RentCollector,
+    missing_accounts: Vec<AccountLoadError>, // accounts the source did not provide, see missing_accounts
+    accounts: HashMap<Pubkey, Result<Option<AccountSharedData>, AccountLoadError>>, // every key is asked to the source once
 }
 
 impl<'a, G> AccountLoader<'a, G> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         g: G,
         loaded_programs: &'a mut LoadedPrograms<ForkGraph>,
         feature_set: &'a FeatureSet,
         program_owners: &'a HashSet<Pubkey>,
         builtin_programs: &'a HashSet<Pubkey>,
+        program_load_errors: &'a mut HashMap<Pubkey, ProgramLoadError>,
+        program_uses: &'a mut ProgramUses,
+        rent_collector: RentCollector,
     ) -> Self {
         Self {
             g,
@@ -31,26 +48,163 @@ impl<'a, G> AccountLoader<'a, G> {
             loaded_programs_cache: loaded_programs,
             program_owners,
             builtin_programs,
+            program_load_errors,
+            program_uses,
+            rent_collector,
+            missing_accounts: vec![],
+            accounts: HashMap::new(),
         }
     }
 
     pub fn from_executor(g: G, e: &'a mut MessageExecutor) -> Self {
-        Self::new(
+        let rent_collector = e.rent_collector();
+        let mut loader = Self::new(
             g,
             &mut e.loaded_programs,
             &e.feature_set,
             &e.program_owners,
             &e.builtin_programs,
-        )
+            &mut e.program_load_errors,
+            &mut e.program_uses,
+            rent_collector,
+        );
+        // Builtins replaced by ELFs and mock programs are never asked to the source
+        loader.accounts.extend(
+            e.program_accounts
+                .iter()
+                .map(|(key, account)| (*key, Ok(Some(account.clone())))),
+        );
+        loader
     }
 }
 
 impl<'a, G> AccountLoader<'a, G>
 where
-    G: FnMut(&Pubkey) -> Option<AccountSharedData>,
+    G: AccountSource,
 {
+    // load_transaction_accounts then replenish_program_cache for a single message. Both read through
+    // the same memo, so the source is asked for each account once.
     #[throws(Error)]
-    fn get_account_with_fixed_root(&mut self, key: &Pubkey) -> Option<AccountSharedData> {
-        (self.g)(&key)
+    pub fn load<S>(
+        &mut self,
+        s: &S,
+        msg: &SanitizedMessage,
+    ) -> (LoadedTransaction, LoadedProgramsForTxBatch)
+    where
+        S: WorkingSlot,
+    {
+        let loaded_transaction = self.load_transaction_accounts(msg)?;
+        let loaded_programs = self.replenish_program_cache(s, [msg])?;
+        (loaded_transaction, loaded_programs)
+    }
+
+    // Accounts that were not found, loaded as empty accounts like the bank does, and accounts the
+    // source failed to provide
+    pub fn missing_accounts(&self) -> &[AccountLoadError] {
+        &self.missing_accounts
+    }
+
+    // Memoizes the source and records what it could not provide
+    fn get_account_with_fixed_root(
+        &mut self,
+        key: &Pubkey,
+    ) -> Result<Option<AccountSharedData>, AccountLoadError> {
+        if let Some(result) = self.accounts.get(key) {
+            return result.clone();
+        }
+
+        let result = self.g.get_account(key);
+        self.accounts.insert(*key, result.clone());
+        let error = match &result {
+            Ok(Some(_)) => return result,
+            Ok(None) => AccountLoadError::NotFound(*key),
+            Err(e) => e.clone(),
+        };
+        if !self.missing_accounts.contains(&error) {
+            self.missing_accounts.push(error);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap};
+
+    use solana_sdk::{
+        account::AccountSharedData,
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        rent::Rent,
+    };
+
+    use super::{
+        super::{
+            system_account,
+            test_utils::{executor, message, source, NOOP_ELF, SLOT},
+            upgradeable_program_accounts, AccountLoadError,
+        },
+        AccountSource,
+    };
+
+    // Counts how often each account is asked for
+    struct CountingSource<'a, G> {
+        g: G,
+        counts: &'a RefCell<HashMap<Pubkey, usize>>,
+    }
+
+    impl<G: AccountSource> AccountSource for CountingSource<'_, G> {
+        fn get_account(
+            &mut self,
+            pubkey: &Pubkey,
+        ) -> Result<Option<AccountSharedData>, AccountLoadError> {
+            *self.counts.borrow_mut().entry(*pubkey).or_default() += 1;
+            self.g.get_account(pubkey)
+        }
+    }
+
+    #[test]
+    fn load_asks_the_source_once_per_account() {
+        let mut sbf = executor();
+        let (payer, program_id, missing) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let program =
+            upgradeable_program_accounts(&program_id, NOOP_ELF, None, 0, &Rent::default()).unwrap();
+        let programdata = program[1].0;
+        let mut accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        accounts.extend(program);
+        let msg = message(
+            &[Instruction::new_with_bytes(
+                program_id,
+                &[],
+                vec![AccountMeta::new_readonly(missing, false)],
+            )],
+            &payer,
+        );
+
+        let counts = RefCell::new(HashMap::new());
+        let working_slot = sbf.working_slot(SLOT);
+        let mut loader = sbf.loader(CountingSource {
+            g: source(&accounts),
+            counts: &counts,
+        });
+        // Loading again is served by the memo
+        for _ in 0..2 {
+            let (loaded_transaction, loaded_programs) = loader.load(&working_slot, &msg).unwrap();
+            // The keys of the message and the loader of the program
+            assert_eq!(loaded_transaction.accounts.len(), 4);
+            assert!(loaded_programs.find(&program_id).is_some());
+        }
+
+        // Including the program data read by the program cache
+        assert!(counts.borrow().contains_key(&programdata));
+        assert!(counts.borrow().values().all(|&count| count == 1));
+        assert_eq!(
+            loader.missing_accounts(),
+            &[AccountLoadError::NotFound(missing)]
+        );
     }
 }
@@ -4,29 +4,27 @@ use anyhow::Error;
 use fehler::throws;
 use solana_program_runtime::loaded_programs::{
     LoadProgramMetrics, LoadedProgram, LoadedProgramMatchCriteria, LoadedProgramType,
-    LoadedProgramsForTxBatch, WorkingSlot, DELAY_VISIBILITY_SLOT_OFFSET,
+    LoadedProgramsForTxBatch, ProgramRuntimeEnvironment, WorkingSlot, DELAY_VISIBILITY_SLOT_OFFSET,
 };
 use solana_sdk::{
     account::{AccountSharedData, ReadableAccount},
     account_utils::StateMut,
     bpf_loader_upgradeable::{self, UpgradeableLoaderState},
-    epoch_schedule::DEFAULT_SLOTS_PER_EPOCH,
     feature_set,
-    instruction::InstructionError,
     loader_v4::{self, LoaderV4State, LoaderV4Status},
     message::SanitizedMessage,
     pubkey::Pubkey,
     slot_history::Slot,
-    transaction::TransactionError,
+    stake_history::Epoch,
 };
 
-use super::AccountLoader;
+use super::{super::ProgramLoadError, AccountLoader, AccountSource};
 
 impl<'a, G> AccountLoader<'a, G>
 where
-    G: FnMut(&Pubkey) -> Option<AccountSharedData>,
+    G: AccountSource,
 {
-    // Bank::replenish_program_cache
+    // Bank::replenish_program_cache, for all the programs of a batch of messages
     #[throws(Error)]
     pub fn replenish_program_cache<'b, I, S>(
         &mut self,
@@ -41,17 +39,26 @@ where
 
         for msg in messages {
             for &key in msg.account_keys().iter() {
-                let acc = self
-                    .get_account_with_fixed_root(&key)?
-                    .ok_or(TransactionError::AccountNotFound)?;
+                // Missing accounts are empty, so they are not programs
+                let acc = match self.get_account_with_fixed_root(&key)? {
+                    Some(acc) => acc,
+                    None => continue,
+                };
+                // Counted once per message, the counts feed the usage counters used for eviction
                 if self.program_owners.contains(&acc.owner()) {
-                    if let Err(i) = missing_programs.binary_search_by_key(&key, |(key, _)| *key) {
-                        missing_programs
-                            .insert(i, (key, (LoadedProgramMatchCriteria::NoCriteria, 0)));
+                    match missing_programs.binary_search_by_key(&key, |(key, _)| *key) {
+                        Ok(i) => {
+                            let (_, count) = &mut missing_programs[i].1;
+                            *count = count.saturating_add(1);
+                        }
+                        Err(i) => missing_programs
+                            .insert(i, (key, (LoadedProgramMatchCriteria::NoCriteria, 1))),
                     }
                 }
             }
         }
+        self.program_uses
+            .record(missing_programs.iter().map(|(key, _)| *key));
         for builtin_program in self.builtin_programs.iter() {
             if let Err(i) = missing_programs.binary_search_by_key(builtin_program, |(key, _)| *key)
             {
@@ -71,18 +78,27 @@ where
         );
 
         // Load programs from cache
+        let searched = missing_programs.len();
         self.loaded_programs_cache.extract(
             s,
             &mut missing_programs,
             &mut loaded_programs_for_txs,
             false,
         );
+        let stats = &self.loaded_programs_cache.stats;
+        stats.hits.fetch_add(
+            (searched - missing_programs.len()) as u64,
+            Ordering::Relaxed,
+        );
+        stats
+            .misses
+            .fetch_add(missing_programs.len() as u64, Ordering::Relaxed);
 
         // Load programs from account
         let loaded_programs: Vec<(Pubkey, Arc<LoadedProgram>)> = missing_programs
             .iter()
             .map(|(key, (_match_criteria, count))| {
-                let program = self.load_program(s.current_slot(), key)?;
+                let program = self.load_program(s.current_slot(), s.current_epoch(), key)?;
                 program.tx_usage_counter.store(*count, Ordering::Relaxed);
                 Result::<_, Error>::Ok((*key, Arc::new(program)))
             })
@@ -145,10 +161,10 @@ where
 
     // Roughly Bank::load_program
     #[throws(Error)]
-    fn load_program(&mut self, slot: Slot, pubkey: &Pubkey) -> LoadedProgram {
+    fn load_program(&mut self, slot: Slot, epoch: Epoch, pubkey: &Pubkey) -> LoadedProgram {
         let environments = self
             .loaded_programs_cache
-            .get_environments_for_epoch(slot / DEFAULT_SLOTS_PER_EPOCH)
+            .get_environments_for_epoch(epoch)
             .clone();
 
         let mut load_program_metrics = LoadProgramMetrics {
@@ -158,21 +174,27 @@ where
 
         let reload = false;
 
-        let loaded_program = match self.load_program_accounts(pubkey)? {
+        let program_account_load_result = self.load_program_accounts(pubkey)?;
+        let tombstone_environment = match &program_account_load_result {
+            ProgramAccountLoadResult::ProgramOfLoaderV4(..)
+            | ProgramAccountLoadResult::InvalidV4Program => environments.program_runtime_v2.clone(),
+            _ => environments.program_runtime_v1.clone(),
+        };
+
+        let loaded_program = match program_account_load_result {
             ProgramAccountLoadResult::AccountNotFound => Ok(LoadedProgram::new_tombstone(
                 slot,
                 LoadedProgramType::Closed,
             )),
 
             ProgramAccountLoadResult::InvalidAccountData => {
-                Err(InstructionError::InvalidAccountData)
+                Err(ProgramLoadError::InvalidAccountData)
             }
 
             ProgramAccountLoadResult::ProgramOfLoaderV1orV2(program_account) => {
-                solana_bpf_loader_program::load_program_from_bytes(
+                load_program_from_bytes(
                     self.feature_set
                         .is_active(&feature_set::delay_visibility_of_program_deployment::id()),
-                    None,
                     &mut load_program_metrics,
                     program_account.data(),
                     program_account.owner(),
@@ -190,12 +212,11 @@ where
             ) => programdata_account
                 .data()
                 .get(UpgradeableLoaderState::size_of_programdata_metadata()..)
-                .ok_or(InstructionError::InvalidAccountData)
+                .ok_or(ProgramLoadError::InvalidProgramData)
                 .and_then(|programdata| {
-                    solana_bpf_loader_program::load_program_from_bytes(
+                    load_program_from_bytes(
                         self.feature_set
                             .is_active(&feature_set::delay_visibility_of_program_deployment::id()),
-                        None,
                         &mut load_program_metrics,
                         programdata,
                         program_account.owner(),
@@ -209,27 +230,15 @@ where
                     )
                 }),
 
-            ProgramAccountLoadResult::ProgramOfLoaderV4(program_account, slot) => {
-                let loaded_program = program_account
-                    .data()
-                    .get(LoaderV4State::program_data_offset()..)
-                    .and_then(|elf_bytes| {
-                        if reload {
-                            // Safety: this is safe because the program is being reloaded in the cache.
-                            unsafe {
-                                LoadedProgram::reload(
-                                    &loader_v4::id(),
-                                    environments.program_runtime_v2.clone(),
-                                    slot,
-                                    slot.saturating_add(DELAY_VISIBILITY_SLOT_OFFSET),
-                                    None,
-                                    elf_bytes,
-                                    program_account.data().len(),
-                                    &mut load_program_metrics,
-                                )
-                            }
-                        } else {
-                            LoadedProgram::new(
+            ProgramAccountLoadResult::ProgramOfLoaderV4(program_account, slot) => program_account
+                .data()
+                .get(LoaderV4State::program_data_offset()..)
+                .ok_or(ProgramLoadError::InvalidV4Program)
+                .and_then(|elf_bytes| {
+                    if reload {
+                        // Safety: this is safe because the program is being reloaded in the cache.
+                        unsafe {
+                            LoadedProgram::reload(
                                 &loader_v4::id(),
                                 environments.program_runtime_v2.clone(),
                                 slot,
@@ -240,33 +249,89 @@ where
                                 &mut load_program_metrics,
                             )
                         }
-                        .ok()
-                    })
-                    .unwrap_or(LoadedProgram::new_tombstone(
-                        slot,
-                        LoadedProgramType::FailedVerification(
+                    } else {
+                        LoadedProgram::new(
+                            &loader_v4::id(),
                             environments.program_runtime_v2.clone(),
-                        ),
-                    ));
-                Ok(loaded_program)
-            }
+                            slot,
+                            slot.saturating_add(DELAY_VISIBILITY_SLOT_OFFSET),
+                            None,
+                            elf_bytes,
+                            program_account.data().len(),
+                            &mut load_program_metrics,
+                        )
+                    }
+                    .map_err(|e| ProgramLoadError::Rejected(e.to_string()))
+                }),
 
-            ProgramAccountLoadResult::InvalidV4Program => Ok(LoadedProgram::new_tombstone(
-                slot,
-                LoadedProgramType::FailedVerification(environments.program_runtime_v2.clone()),
-            )),
-        }
-        .unwrap_or_else(|_| {
-            LoadedProgram::new_tombstone(
-                slot,
-                LoadedProgramType::FailedVerification(environments.program_runtime_v1.clone()),
-            )
-        });
+            ProgramAccountLoadResult::InvalidV4Program => Err(ProgramLoadError::InvalidV4Program),
+        };
+
+        // Keep the reason of the failure around, the cache only stores the tombstone
+        let loaded_program = match loaded_program {
+            Ok(loaded_program) => {
+                self.program_load_errors.remove(pubkey);
+                loaded_program
+            }
+            Err(e) => {
+                self.program_load_errors.insert(*pubkey, e);
+                LoadedProgram::new_tombstone(
+                    slot,
+                    LoadedProgramType::FailedVerification(tombstone_environment),
+                )
+            }
+        };
 
         loaded_program
     }
 }
 
+// solana_bpf_loader_program::load_program_from_bytes, keeping the error instead of logging it
+#[allow(clippy::too_many_arguments)]
+fn load_program_from_bytes(
+    delay_visibility_of_program_deployment: bool,
+    load_program_metrics: &mut LoadProgramMetrics,
+    programdata: &[u8],
+    loader_key: &Pubkey,
+    account_size: usize,
+    deployment_slot: Slot,
+    program_runtime_environment: ProgramRuntimeEnvironment,
+    reloading: bool,
+) -> Result<LoadedProgram, ProgramLoadError> {
+    let effective_slot = if delay_visibility_of_program_deployment {
+        deployment_slot.saturating_add(DELAY_VISIBILITY_SLOT_OFFSET)
+    } else {
+        deployment_slot
+    };
+    if reloading {
+        // Safety: this is safe because the program is being reloaded in the cache.
+        unsafe {
+            LoadedProgram::reload(
+                loader_key,
+                program_runtime_environment,
+                deployment_slot,
+                effective_slot,
+                None,
+                programdata,
+                account_size,
+                load_program_metrics,
+            )
+        }
+    } else {
+        LoadedProgram::new(
+            loader_key,
+            program_runtime_environment,
+            deployment_slot,
+            effective_slot,
+            None,
+            programdata,
+            account_size,
+            load_program_metrics,
+        )
+    }
+    .map_err(|e| ProgramLoadError::Rejected(e.to_string()))
+}
+
 enum ProgramAccountLoadResult {
     AccountNotFound,
     InvalidAccountData,
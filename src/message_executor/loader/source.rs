@@ -0,0 +1,338 @@
+use std::{collections::HashMap, thread, time::Duration};
+
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_client::RpcClient,
+    rpc_request::MAX_MULTIPLE_ACCOUNTS,
+};
+use solana_sdk::{
+    account::{AccountSharedData, ReadableAccount},
+    account_utils::StateMut,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    message::SanitizedMessage,
+    pubkey::Pubkey,
+};
+
+use super::super::AccountLoadError;
+
+// Where AccountLoader gets accounts from. Ok(None) means the account does not exist.
+pub trait AccountSource {
+    fn get_account(
+        &mut self,
+        pubkey: &Pubkey,
+    ) -> Result<Option<AccountSharedData>, AccountLoadError>;
+}
+
+// Closures are infallible sources
+impl<F> AccountSource for F
+where
+    F: FnMut(&Pubkey) -> Option<AccountSharedData>,
+{
+    fn get_account(
+        &mut self,
+        pubkey: &Pubkey,
+    ) -> Result<Option<AccountSharedData>, AccountLoadError> {
+        Ok(self(pubkey))
+    }
+}
+
+// Retries of transient RPC failures like timeouts and dropped connections, with exponential backoff
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+// Fetches accounts from an RPC node once per run, on top of local overrides
+pub struct RpcAccountSource<'a> {
+    rpc: &'a RpcClient,
+    retry_policy: RetryPolicy,
+    accounts: HashMap<Pubkey, Option<AccountSharedData>>,
+}
+
+// ClientError is large, but it is what RpcClient returns
+#[allow(clippy::result_large_err)]
+impl<'a> RpcAccountSource<'a> {
+    pub fn new(rpc: &'a RpcClient) -> Self {
+        Self {
+            rpc,
+            retry_policy: RetryPolicy::default(),
+            accounts: HashMap::new(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    // Serve this account instead of the one on the node
+    pub fn insert(&mut self, pubkey: Pubkey, account: AccountSharedData) {
+        self.accounts.insert(pubkey, Some(account));
+    }
+
+    // Fetches the accounts of `message`, then the program data and loaders of its programs, with
+    // getMultipleAccounts so that the loader finds them in the cache
+    pub fn prefetch(&mut self, message: &SanitizedMessage) {
+        let keys: Vec<_> = message.account_keys().iter().copied().collect();
+        self.prefetch_accounts(&keys);
+
+        let mut program_keys = vec![];
+        for key in &keys {
+            let Some(Some(account)) = self.accounts.get(key) else {
+                continue;
+            };
+            if let Ok(UpgradeableLoaderState::Program {
+                programdata_address,
+            }) = account.state()
+            {
+                if bpf_loader_upgradeable::check_id(account.owner()) {
+                    program_keys.push(programdata_address);
+                }
+            }
+            if account.executable() {
+                program_keys.push(*account.owner());
+            }
+        }
+        self.prefetch_accounts(&program_keys);
+    }
+
+    // Fetches `keys` with getMultipleAccounts. Chunks that fail are left to get_account, which reports
+    // the failure of each account.
+    pub fn prefetch_accounts(&mut self, keys: &[Pubkey]) {
+        let mut keys: Vec<_> = keys
+            .iter()
+            .filter(|key| !self.accounts.contains_key(key))
+            .copied()
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let Ok(accounts) = self.with_retries(|rpc| {
+                rpc.get_multiple_accounts_with_commitment(chunk, rpc.commitment())
+            }) else {
+                continue;
+            };
+            for (key, account) in chunk.iter().zip(accounts.value) {
+                self.accounts
+                    .insert(*key, account.map(AccountSharedData::from));
+            }
+        }
+    }
+
+    fn with_retries<T>(&self, f: impl Fn(&RpcClient) -> ClientResult<T>) -> ClientResult<T> {
+        let mut backoff = self.retry_policy.initial_backoff;
+        let mut retries = 0;
+        loop {
+            match f(self.rpc) {
+                Err(e) if is_transient(&e) && retries < self.retry_policy.max_retries => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl<'a> AccountSource for RpcAccountSource<'a> {
+    fn get_account(
+        &mut self,
+        pubkey: &Pubkey,
+    ) -> Result<Option<AccountSharedData>, AccountLoadError> {
+        if let Some(account) = self.accounts.get(pubkey) {
+            return Ok(account.clone());
+        }
+
+        let account = self
+            .with_retries(|rpc| rpc.get_account_with_commitment(pubkey, rpc.commitment()))
+            .map_err(|e| AccountLoadError::SourceFailure {
+                pubkey: *pubkey,
+                reason: e.to_string(),
+            })?
+            .value
+            .map(AccountSharedData::from);
+
+        self.accounts.insert(*pubkey, account.clone());
+        Ok(account)
+    }
+}
+
+fn is_transient(e: &ClientError) -> bool {
+    matches!(
+        e.kind(),
+        ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_)
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::result_large_err)]
+mod tests {
+    use std::{
+        cell::Cell,
+        collections::HashMap,
+        io::{self, ErrorKind},
+        time::Duration,
+    };
+
+    use serde_json::json;
+    use solana_account_decoder::{UiAccount, UiAccountEncoding};
+    use solana_client::{
+        client_error::{ClientError, ClientErrorKind},
+        rpc_client::RpcClient,
+        rpc_request::{RpcRequest, MAX_MULTIPLE_ACCOUNTS},
+    };
+    use solana_sdk::{account::AccountSharedData, pubkey::Pubkey};
+
+    use super::{
+        super::super::{system_account, AccountLoadError},
+        AccountSource, RetryPolicy, RpcAccountSource,
+    };
+
+    const NO_BACKOFF: RetryPolicy = RetryPolicy {
+        max_retries: 2,
+        initial_backoff: Duration::ZERO,
+    };
+
+    fn timeout() -> ClientError {
+        io::Error::new(ErrorKind::TimedOut, "timed out").into()
+    }
+
+    fn response(value: serde_json::Value) -> serde_json::Value {
+        json!({"context": {"slot": 1}, "value": value})
+    }
+
+    fn ui_account(pubkey: &Pubkey, account: &AccountSharedData) -> UiAccount {
+        UiAccount::encode(pubkey, account, UiAccountEncoding::Base64, None, None)
+    }
+
+    #[test]
+    fn only_transient_failures_are_retried() {
+        let rpc = RpcClient::new_mock("succeeds");
+        let source = RpcAccountSource::new(&rpc).with_retry_policy(NO_BACKOFF);
+        let attempts = Cell::new(0);
+
+        let result: Result<(), _> = source.with_retries(|_| {
+            attempts.set(attempts.get() + 1);
+            Err(timeout())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.replace(0), 3);
+
+        let result: Result<(), _> = source.with_retries(|_| {
+            attempts.set(attempts.get() + 1);
+            Err(ClientErrorKind::Custom("rejected".to_string()).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.replace(0), 1);
+
+        let result = source.with_retries(|_| {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(timeout()),
+                _ => Ok(()),
+            }
+        });
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn missing_accounts_are_not_failures() {
+        let pubkey = Pubkey::new_unique();
+
+        let rpc = RpcClient::new_mock("succeeds");
+        let mut source = RpcAccountSource::new(&rpc);
+        assert_eq!(source.get_account(&pubkey), Ok(None));
+
+        let account = system_account(1_000_000);
+        let mocks = HashMap::from([(
+            RpcRequest::GetAccountInfo,
+            response(json!(ui_account(&pubkey, &account))),
+        )]);
+        let rpc = RpcClient::new_mock_with_mocks("succeeds", mocks);
+        let mut source = RpcAccountSource::new(&rpc);
+        assert_eq!(source.get_account(&pubkey), Ok(Some(account.clone())));
+
+        // Answers that cannot be parsed are failures of the source
+        let rpc = RpcClient::new_mock("fails");
+        let mut source = RpcAccountSource::new(&rpc).with_retry_policy(NO_BACKOFF);
+        assert!(matches!(
+            source.get_account(&pubkey),
+            Err(AccountLoadError::SourceFailure { pubkey: failed, .. }) if failed == pubkey
+        ));
+
+        // Local accounts are served without asking the node
+        source.insert(pubkey, account.clone());
+        assert_eq!(source.get_account(&pubkey), Ok(Some(account)));
+    }
+
+    #[test]
+    fn prefetch_in_chunks() {
+        let mut keys: Vec<_> = (0..MAX_MULTIPLE_ACCOUNTS + 2)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        keys.sort_unstable();
+        let account = system_account(1_000_000);
+
+        // Only the first request is answered with accounts, the mock answers the next ones with
+        // two missing accounts
+        let first_chunk: Vec<_> = keys[..MAX_MULTIPLE_ACCOUNTS]
+            .iter()
+            .map(|key| ui_account(key, &account))
+            .collect();
+        let mocks = HashMap::from([(
+            RpcRequest::GetMultipleAccounts,
+            response(json!(first_chunk)),
+        )]);
+        let rpc = RpcClient::new_mock_with_mocks("succeeds", mocks);
+        let mut source = RpcAccountSource::new(&rpc);
+        let local = Pubkey::new_unique();
+        source.insert(local, account.clone());
+
+        // Duplicates and local accounts are not asked for
+        let mut requested = keys.clone();
+        requested.extend([keys[0], local]);
+        source.prefetch_accounts(&requested);
+        assert_eq!(source.accounts.len(), keys.len() + 1);
+        for key in &keys[..MAX_MULTIPLE_ACCOUNTS] {
+            assert_eq!(source.accounts[key], Some(account.clone()));
+        }
+        for key in &keys[MAX_MULTIPLE_ACCOUNTS..] {
+            assert_eq!(source.accounts[key], None);
+        }
+    }
+
+    #[test]
+    fn failed_chunks_fall_back_to_single_accounts() {
+        let (pubkey, missing) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let account = system_account(1_000_000);
+        let mocks = HashMap::from([
+            // Cannot be parsed
+            (RpcRequest::GetMultipleAccounts, serde_json::Value::Null),
+            (
+                RpcRequest::GetAccountInfo,
+                response(json!(ui_account(&pubkey, &account))),
+            ),
+        ]);
+        let rpc = RpcClient::new_mock_with_mocks("succeeds", mocks);
+        let mut source = RpcAccountSource::new(&rpc).with_retry_policy(NO_BACKOFF);
+
+        source.prefetch_accounts(&[pubkey, missing]);
+        assert!(source.accounts.is_empty());
+        assert_eq!(source.get_account(&pubkey), Ok(Some(account)));
+        assert_eq!(source.get_account(&missing), Ok(None));
+    }
+}
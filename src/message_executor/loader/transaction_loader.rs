@@ -1,21 +1,25 @@
 use anyhow::Error;
 use fehler::{throw, throws};
-use solana_accounts_db::accounts::LoadedTransaction;
+use solana_accounts_db::{accounts::LoadedTransaction, rent_debits::RentDebits};
 use solana_sdk::{
     account::{Account, AccountSharedData, ReadableAccount},
+    feature_set::set_exempt_rent_epoch_max,
     message::SanitizedMessage,
     native_loader,
-    pubkey::Pubkey,
+    precompiles::is_precompile,
     sysvar,
     sysvar::instructions::construct_instructions_data,
     transaction::TransactionError,
 };
 
-use super::AccountLoader;
+use super::{
+    super::{native_program_account, MessageExecutorError},
+    AccountLoader, AccountSource,
+};
 
 impl<'a, G> AccountLoader<'a, G>
 where
-    G: FnMut(&Pubkey) -> Option<AccountSharedData>,
+    G: AccountSource,
 {
     fn construct_instructions_account(message: &SanitizedMessage) -> AccountSharedData {
         AccountSharedData::from(Account {
@@ -30,21 +34,56 @@ where
     pub fn load_transaction_accounts(&mut self, msg: &SanitizedMessage) -> LoadedTransaction {
         let mut accounts =
             Vec::with_capacity(msg.account_keys().len() + msg.instructions().len() * 2);
+        let mut tx_rent = 0;
+        let mut rent_debits = RentDebits::default();
+
+        let set_exempt_rent_epoch_max =
+            self.feature_set.is_active(&set_exempt_rent_epoch_max::id());
 
-        for &key in msg.account_keys().iter() {
+        let mut unavailable = vec![];
+        for (i, &key) in msg.account_keys().iter().enumerate() {
             if solana_sdk::sysvar::instructions::check_id(&key) {
                 let acc = Self::construct_instructions_account(msg);
                 accounts.push((key, acc.into()));
                 continue;
             }
 
-            let account = self
-                .get_account_with_fixed_root(&key)?
-                .ok_or(TransactionError::AccountNotFound)?;
+            let mut account = match self.get_account_with_fixed_root(&key) {
+                Ok(Some(account)) => account,
+                // Precompiles are never executed, so a placeholder is enough when the source lacks them
+                Ok(None) if is_precompile(&key, |id| self.feature_set.is_active(id)) => {
+                    native_program_account(&key.to_string())
+                }
+                // Like the bank, a missing fee payer fails in process with AccountNotFound
+                Ok(None) => AccountSharedData::default(),
+                Err(e) => {
+                    unavailable.push(e);
+                    continue;
+                }
+            };
+
+            if msg.is_writable(i) {
+                let rent_due = self
+                    .rent_collector
+                    .collect_from_existing_account(
+                        &key,
+                        &mut account,
+                        None,
+                        set_exempt_rent_epoch_max,
+                    )
+                    .rent_amount;
+                tx_rent += rent_due;
+                rent_debits.insert(&key, rent_due, account.lamports());
+            }
 
             accounts.push((key, account));
         }
 
+        // Report every account the source failed on rather than just the first one
+        if !unavailable.is_empty() {
+            throw!(MessageExecutorError::AccountsUnavailable(unavailable));
+        }
+
         let builtins_start_index = accounts.len();
         let mut program_indices = Vec::with_capacity(msg.instructions().len());
         'OUTER: for ix in msg.instructions() {
@@ -103,8 +142,8 @@ where
         LoadedTransaction {
             accounts,
             program_indices,
-            rent: 0,
-            rent_debits: Default::default(),
+            rent: tx_rent,
+            rent_debits,
         }
     }
 }
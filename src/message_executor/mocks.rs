@@ -0,0 +1,195 @@
+use std::{cell::RefCell, collections::HashMap, sync::Arc};
+
+use solana_program_runtime::{declare_process_instruction, stable_log};
+use solana_sdk::{
+    instruction::InstructionError, pubkey::Pubkey, transaction_context::IndexOfAccount,
+};
+
+// A program implemented by a Rust closure, see MessageExecutor::add_mock_program
+pub type MockProgram = dyn Fn(&mut MockInvocation) -> Result<(), InstructionError> + Send + Sync;
+
+// What a mock program is invoked with, top-level or through CPI. Changes to the accounts and the
+// return data are applied when the closure returns Ok, under the same rules as for real programs,
+// e.g. only the data of accounts owned by the mock program can change.
+#[derive(Debug, Clone)]
+pub struct MockInvocation {
+    pub program_id: Pubkey,
+    pub data: Vec<u8>,
+    pub accounts: Vec<MockAccount>, // in the order of the instruction
+    pub return_data: Option<Vec<u8>>,
+    pub logs: Vec<String>, // written as "Program log: " messages, also when the closure fails
+}
+
+#[derive(Debug, Clone)]
+pub struct MockAccount {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+    pub lamports: u64,
+    pub owner: Pubkey,
+    pub data: Vec<u8>,
+}
+
+thread_local! {
+    // The mock programs of the executor processing a message on this thread
+    static MOCK_PROGRAMS: RefCell<HashMap<Pubkey, Arc<MockProgram>>> = RefCell::default();
+}
+
+// Makes `programs` reachable from the entrypoint until dropped. The entrypoint is a plain function
+// shared by every mock program, so the closures are looked up by program id.
+pub(crate) struct MockScope {
+    previous: HashMap<Pubkey, Arc<MockProgram>>,
+}
+
+impl MockScope {
+    pub(crate) fn enter(programs: &HashMap<Pubkey, Arc<MockProgram>>) -> Self {
+        let previous = MOCK_PROGRAMS.with(|mocks| mocks.replace(programs.clone()));
+        Self { previous }
+    }
+}
+
+impl Drop for MockScope {
+    fn drop(&mut self) {
+        MOCK_PROGRAMS.with(|mocks| *mocks.borrow_mut() = std::mem::take(&mut self.previous));
+    }
+}
+
+declare_process_instruction!(Entrypoint, 0, |invoke_context| {
+    let transaction_context = &invoke_context.transaction_context;
+    let instruction_context = transaction_context.get_current_instruction_context()?;
+    let program_id = *instruction_context.get_last_program_key(transaction_context)?;
+    let program = MOCK_PROGRAMS
+        .with(|mocks| mocks.borrow().get(&program_id).cloned())
+        .ok_or(InstructionError::UnsupportedProgramId)?;
+
+    let mut accounts = vec![];
+    for index in 0..instruction_context.get_number_of_instruction_accounts() {
+        let account =
+            instruction_context.try_borrow_instruction_account(transaction_context, index)?;
+        accounts.push(MockAccount {
+            pubkey: *account.get_key(),
+            is_signer: account.is_signer(),
+            is_writable: account.is_writable(),
+            lamports: account.get_lamports(),
+            owner: *account.get_owner(),
+            data: account.get_data().to_vec(),
+        });
+    }
+    let mut invocation = MockInvocation {
+        program_id,
+        data: instruction_context.get_instruction_data().to_vec(),
+        accounts,
+        return_data: None,
+        logs: vec![],
+    };
+
+    let result = program(&mut invocation);
+    for log in &invocation.logs {
+        stable_log::program_log(&invoke_context.get_log_collector(), log);
+    }
+    result?;
+
+    // Only what changed is written, the owner last as the data can only change before
+    let transaction_context = &invoke_context.transaction_context;
+    let instruction_context = transaction_context.get_current_instruction_context()?;
+    for (index, view) in invocation.accounts.iter().enumerate() {
+        let mut account = instruction_context
+            .try_borrow_instruction_account(transaction_context, index as IndexOfAccount)?;
+        if account.get_lamports() != view.lamports {
+            account.set_lamports(view.lamports)?;
+        }
+        if account.get_data() != view.data.as_slice() {
+            account.set_data_from_slice(&view.data)?;
+        }
+        if *account.get_owner() != view.owner {
+            account.set_owner(view.owner.as_ref())?;
+        }
+    }
+    if let Some(return_data) = invocation.return_data {
+        invoke_context
+            .transaction_context
+            .set_return_data(program_id, return_data)?;
+    }
+    Ok(())
+});
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_sdk::{
+        account::{AccountSharedData, ReadableAccount},
+        instruction::{AccountMeta, Instruction, InstructionError},
+        pubkey::Pubkey,
+        rent::Rent,
+        system_program,
+    };
+
+    use super::super::{
+        system_account,
+        test_utils::{executor, message, run},
+    };
+
+    #[test]
+    fn mock_program_writes_accounts_and_return_data() {
+        let mut sbf = executor();
+        let mock = Pubkey::new_unique();
+        sbf.add_mock_program(mock, |invocation| {
+            let [vault, recipient, orphan] = invocation.accounts.as_mut_slice() else {
+                return Err(InstructionError::NotEnoughAccountKeys);
+            };
+            vault.lamports -= 1_000_000;
+            recipient.lamports += 1_000_000;
+            vault.data.copy_from_slice(&invocation.data);
+            orphan.owner = system_program::id();
+            invocation.return_data = Some(vec![7, 7]);
+            invocation.logs.push("moved".to_string());
+            Ok(())
+        });
+
+        let rent = Rent::default();
+        let (payer, vault, orphan) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let vault_lamports = rent.minimum_balance(4) + 1_000_000;
+        let accounts = HashMap::from([
+            (payer, system_account(1_000_000_000)),
+            (vault, AccountSharedData::new(vault_lamports, 4, &mock)),
+            (
+                orphan,
+                AccountSharedData::new(rent.minimum_balance(0), 0, &mock),
+            ),
+        ]);
+
+        let ix = Instruction::new_with_bytes(
+            mock,
+            &[1, 2, 3, 4],
+            vec![
+                AccountMeta::new(vault, false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new(orphan, false),
+            ],
+        );
+        let record = run(&mut sbf, &message(&[ix], &payer), &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+
+        let account = |key: &Pubkey| {
+            let index = record.keys.iter().position(|k| k == key).unwrap();
+            &record.datas[index]
+        };
+        assert_eq!(account(&vault).lamports(), vault_lamports - 1_000_000);
+        assert_eq!(account(&vault).data(), &[1, 2, 3, 4]);
+        assert_eq!(
+            account(&payer).lamports(),
+            1_000_000_000 - record.fee + 1_000_000
+        );
+        assert_eq!(account(&orphan).owner(), &system_program::id());
+
+        let return_data = record.return_data.unwrap();
+        assert_eq!(return_data.program_id, mock);
+        assert_eq!(return_data.data, vec![7, 7]);
+        assert!(record.logs[0].contains(&"Program log: moved".to_string()));
+    }
+}
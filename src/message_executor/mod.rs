@@ -1,17 +1,47 @@
+mod accounts;
+mod address_loader;
+mod builtins;
+mod diff;
 mod errors;
 mod executor;
+mod features;
+mod inner_instructions;
 mod loader;
+mod message_processor;
+mod mocks;
+mod options;
+mod pool;
+mod program_cache;
+mod programs;
+mod rpc;
+mod sysvars;
+#[cfg(test)]
+mod test_utils;
+mod transaction_account_state_info;
 
-pub use errors::MessageExecutorError;
+pub use accounts::{native_program_account, system_account, token_account, token_mint_account};
+pub use address_loader::LookupTableLoader;
+pub use builtins::BuiltinSet;
+pub use diff::{AccountDiff, TokenBalanceDiff};
+pub use errors::{AccountLoadError, MessageExecutorError, ProgramLoadError};
 pub use executor::{ExecutionRecord, MessageExecutor};
-pub use loader::AccountLoader;
+pub use features::feature_set_from_accounts;
+pub use inner_instructions::{InnerInstruction, InnerInstructions};
+pub use loader::{AccountLoader, AccountSource, RetryPolicy, RpcAccountSource};
+pub use mocks::{MockAccount, MockInvocation, MockProgram};
+pub use options::{ProcessOptions, LOG_MESSAGES_BYTES_LIMIT};
+pub use pool::ExecutorPool;
+pub use program_cache::{ProgramCacheStats, ProgramUses};
+pub use programs::{
+    loader_v4_program_account, upgradeable_buffer_account, upgradeable_program_accounts,
+};
+pub use rpc::RpcSimulateTransactionResult;
 
-use std::cmp::Ordering;
+use std::{cmp::Ordering, collections::HashSet};
 
 use solana_program_runtime::loaded_programs::{self, BlockRelation};
 use solana_sdk::{
-    epoch_schedule::DEFAULT_SLOTS_PER_EPOCH, pubkey, pubkey::Pubkey, slot_history::Slot,
-    stake_history::Epoch,
+    epoch_schedule::EpochSchedule, pubkey, pubkey::Pubkey, slot_history::Slot, stake_history::Epoch,
 };
 
 pub const FEATURES: &'static [Pubkey] = &[
@@ -125,25 +155,73 @@ pub const FEATURES: &'static [Pubkey] = &[
     pubkey!("7Vced912WrRnfjaiKRiNBcbuFw7RrnLv3E3z95Y4GTNc"),
 ];
 
-pub struct WorkingSlot(pub Slot);
+// The slot a batch is loaded for. Without explicit ancestors every lower slot is an ancestor.
+pub struct WorkingSlot {
+    slot: Slot,
+    epoch_schedule: EpochSchedule,
+    ancestors: Option<HashSet<Slot>>,
+}
+
+impl WorkingSlot {
+    pub fn new(slot: Slot, epoch_schedule: EpochSchedule) -> Self {
+        Self {
+            slot,
+            epoch_schedule,
+            ancestors: None,
+        }
+    }
+
+    pub fn with_ancestors<I: IntoIterator<Item = Slot>>(mut self, ancestors: I) -> Self {
+        self.ancestors = Some(ancestors.into_iter().collect());
+        self
+    }
+}
+
 impl loaded_programs::WorkingSlot for WorkingSlot {
     fn current_slot(&self) -> Slot {
-        self.0
+        self.slot
     }
 
     fn current_epoch(&self) -> Epoch {
-        self.0 / DEFAULT_SLOTS_PER_EPOCH
+        self.epoch_schedule.get_epoch(self.slot)
     }
 
     fn is_ancestor(&self, slot: Slot) -> bool {
-        slot < self.0
+        match &self.ancestors {
+            Some(ancestors) => ancestors.contains(&slot),
+            None => slot < self.slot,
+        }
     }
 }
 
-pub struct ForkGraph;
+// A single simulated fork. Without explicit slots every pair of slots is on the fork.
+pub struct ForkGraph {
+    epoch_schedule: EpochSchedule,
+    slots: Option<HashSet<Slot>>,
+}
+
+impl ForkGraph {
+    pub fn new(epoch_schedule: EpochSchedule) -> Self {
+        Self {
+            epoch_schedule,
+            slots: None,
+        }
+    }
+
+    pub fn with_slots<I: IntoIterator<Item = Slot>>(mut self, slots: I) -> Self {
+        self.slots = Some(slots.into_iter().collect());
+        self
+    }
+}
 
 impl loaded_programs::ForkGraph for ForkGraph {
     fn relationship(&self, a: Slot, b: Slot) -> BlockRelation {
+        if let Some(slots) = &self.slots {
+            if a != b && !(slots.contains(&a) && slots.contains(&b)) {
+                return BlockRelation::Unrelated;
+            }
+        }
+
         match a.cmp(&b) {
             Ordering::Equal => BlockRelation::Equal,
             Ordering::Less => BlockRelation::Ancestor,
@@ -152,6 +230,6 @@ impl loaded_programs::ForkGraph for ForkGraph {
     }
 
     fn slot_epoch(&self, slot: Slot) -> Option<Epoch> {
-        Some(slot / DEFAULT_SLOTS_PER_EPOCH)
+        Some(self.epoch_schedule.get_epoch(slot))
     }
 }
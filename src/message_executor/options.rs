@@ -0,0 +1,184 @@
+use std::str::FromStr;
+
+use solana_sdk::pubkey::Pubkey;
+
+// The byte limit of the runtime's LogCollector, which does not export it
+pub const LOG_MESSAGES_BYTES_LIMIT: usize = 10 * 1000;
+
+// How a single MessageExecutor::process_with_options call records logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessOptions {
+    // Without logs the records have no compute units for inner instructions
+    pub capture_logs: bool,
+    // Like the runtime, the log that would exceed the limit and every later one are replaced by a
+    // single "Log truncated", None to keep everything
+    pub log_bytes_limit: Option<usize>,
+    // Keep the "invoke", "consumed", "success", "failed" and "return" messages of the runtime,
+    // otherwise only the messages of the programs are returned
+    pub stable_log: bool,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            capture_logs: true,
+            log_bytes_limit: Some(LOG_MESSAGES_BYTES_LIMIT),
+            stable_log: true,
+        }
+    }
+}
+
+impl ProcessOptions {
+    // No logs at all, the fastest
+    pub fn without_logs() -> Self {
+        Self {
+            capture_logs: false,
+            ..Self::default()
+        }
+    }
+
+    // Applies the byte limit and the stable_log filter to the logs split by instruction. The logs
+    // are captured whole so that the inner instructions and the groups do not depend on the limit.
+    pub(crate) fn apply(&self, logs: &mut [Vec<String>]) {
+        if let Some(limit) = self.log_bytes_limit {
+            // LogCollector::log over the concatenated groups
+            let mut bytes_written = 0usize;
+            let mut truncated = false;
+            for group in logs.iter_mut() {
+                group.retain_mut(|log| {
+                    if truncated {
+                        return false;
+                    }
+                    let written = bytes_written.saturating_add(log.len());
+                    if written >= limit {
+                        truncated = true;
+                        *log = String::from("Log truncated");
+                    } else {
+                        bytes_written = written;
+                    }
+                    true
+                });
+            }
+        }
+        if !self.stable_log {
+            for group in logs.iter_mut() {
+                group.retain(|log| !is_stable_log(log));
+            }
+        }
+    }
+}
+
+// The messages of solana_program_runtime::stable_log that are not written by programs
+fn is_stable_log(log: &str) -> bool {
+    if log.starts_with("Program return: ") {
+        return true;
+    }
+    match log.split_whitespace().collect::<Vec<_>>().as_slice() {
+        // Program logs start with "Program log:" or "Program data:" instead of a program id
+        ["Program", program_id, rest @ ..] if Pubkey::from_str(program_id).is_ok() => matches!(
+            rest,
+            ["invoke", _]
+                | ["success"]
+                | ["failed:", ..]
+                | ["consumed", _, "of", _, "compute", "units"]
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+    };
+
+    use super::{
+        super::{
+            system_account,
+            test_utils::{executor, message, source, SLOT},
+            ExecutionRecord, MessageExecutor,
+        },
+        ProcessOptions,
+    };
+
+    fn process(
+        sbf: &mut MessageExecutor,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        options: &ProcessOptions,
+    ) -> ExecutionRecord {
+        let accounts = HashMap::from([(*payer, system_account(1_000_000_000))]);
+        let message = message(instructions, payer);
+        let working_slot = sbf.working_slot(SLOT);
+        let (loaded_transaction, loaded_programs) = sbf
+            .loader(source(&accounts))
+            .load(&working_slot, &message)
+            .unwrap();
+        sbf.process_with_options(
+            SLOT,
+            &message,
+            loaded_transaction,
+            &loaded_programs,
+            options,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn truncated_logs_keep_the_instruction_groups() {
+        let mut sbf = executor();
+        let mock = Pubkey::new_unique();
+        sbf.add_mock_program(mock, |invocation| {
+            invocation.logs.push("x".repeat(100));
+            Ok(())
+        });
+        let payer = Pubkey::new_unique();
+        let instructions: Vec<_> = (0..3u8)
+            .map(|i| Instruction::new_with_bytes(mock, &[i], vec![AccountMeta::new(payer, true)]))
+            .collect();
+
+        let whole = ProcessOptions {
+            log_bytes_limit: None,
+            ..ProcessOptions::default()
+        };
+        let full = process(&mut sbf, &instructions, &payer, &whole);
+        let limited = ProcessOptions {
+            log_bytes_limit: Some(300),
+            ..ProcessOptions::default()
+        };
+        let truncated = process(&mut sbf, &instructions, &payer, &limited);
+
+        assert_eq!(full.status, Ok(()));
+        assert_eq!(truncated.status, Ok(()));
+        assert_eq!(full.logs.len(), 3);
+        assert_eq!(truncated.logs.len(), 3);
+        assert_eq!(
+            full.inner_instructions.len(),
+            truncated.inner_instructions.len()
+        );
+
+        // The first instruction fits, the second is cut, the third has no room left
+        assert_eq!(truncated.logs[0], full.logs[0]);
+        assert_eq!(truncated.logs[1].last().unwrap(), "Log truncated");
+        assert!(full.logs[1].starts_with(&truncated.logs[1][..truncated.logs[1].len() - 1]));
+        assert!(truncated.logs[2].is_empty());
+        let flat: Vec<_> = truncated.logs.concat();
+        assert_eq!(flat.iter().filter(|log| *log == "Log truncated").count(), 1);
+        assert!(flat.iter().map(|log| log.len()).sum::<usize>() < 300 + "Log truncated".len());
+
+        let program_logs_only = ProcessOptions {
+            stable_log: false,
+            ..limited
+        };
+        let filtered = process(&mut sbf, &instructions, &payer, &program_logs_only);
+        assert_eq!(filtered.logs.len(), 3);
+        assert_eq!(
+            filtered.logs[0],
+            vec![format!("Program log: {}", "x".repeat(100))]
+        );
+        assert_eq!(filtered.logs[1].last().unwrap(), "Log truncated");
+    }
+}
@@ -0,0 +1,158 @@
+use std::{panic, thread};
+
+use anyhow::Error;
+use getset::{Getters, MutGetters};
+use solana_sdk::{message::SanitizedMessage, slot_history::Slot};
+
+use super::{AccountSource, ExecutionRecord, MessageExecutor, ProcessOptions};
+
+// Simulates independent messages in parallel on one executor, so that they share its program cache,
+// feature set and sysvars. Nothing is committed, every message runs against the accounts of `g`.
+#[derive(Getters, MutGetters)]
+pub struct ExecutorPool {
+    #[getset(get = "pub", get_mut = "pub")]
+    executor: MessageExecutor,
+    threads: usize,
+    #[getset(get = "pub")]
+    options: ProcessOptions, // applied to every message
+}
+
+impl ExecutorPool {
+    pub fn new(executor: MessageExecutor, threads: usize) -> Self {
+        Self {
+            executor,
+            threads: threads.max(1),
+            options: ProcessOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ProcessOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // One thread per available core
+    pub fn with_available_parallelism(executor: MessageExecutor) -> Self {
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        Self::new(executor, threads)
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn into_executor(self) -> MessageExecutor {
+        self.executor
+    }
+
+    // The records of `messages`, in order. Accounts and programs are loaded on the calling thread,
+    // so `g` is asked for each account once, then the messages are processed on `threads` threads.
+    pub fn simulate_many<G>(
+        &mut self,
+        slot: Slot,
+        messages: &[SanitizedMessage],
+        g: G,
+    ) -> Vec<Result<ExecutionRecord, Error>>
+    where
+        G: AccountSource,
+    {
+        let working_slot = self.executor.working_slot(slot);
+        let mut loader = self.executor.loader(g);
+        let loaded: Vec<_> = messages
+            .iter()
+            .map(|message| loader.load(&working_slot, message))
+            .collect();
+
+        let (executor, options) = (&self.executor, &self.options);
+        #[allow(clippy::manual_div_ceil)] // usize::div_ceil needs Rust 1.73
+        let chunk_size = ((messages.len() + self.threads - 1) / self.threads).max(1);
+        let mut jobs = messages.iter().zip(loaded);
+        thread::scope(|s| {
+            let mut handles = vec![];
+            loop {
+                let chunk: Vec<_> = jobs.by_ref().take(chunk_size).collect();
+                if chunk.is_empty() {
+                    break;
+                }
+                handles.push(s.spawn(move || {
+                    chunk
+                        .into_iter()
+                        .map(|(message, loaded)| {
+                            let (loaded_transaction, loaded_programs) = loaded?;
+                            executor.process_with_options(
+                                slot,
+                                message,
+                                loaded_transaction,
+                                &loaded_programs,
+                                options,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                }));
+            }
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_sdk::{account::ReadableAccount, pubkey::Pubkey, system_instruction};
+
+    use super::{
+        super::{
+            system_account,
+            test_utils::{executor, message, source, SLOT},
+        },
+        ExecutorPool,
+    };
+
+    #[test]
+    fn records_are_in_input_order() {
+        let payer = Pubkey::new_unique();
+        let accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        let recipients: Vec<_> = (0..5).map(|_| Pubkey::new_unique()).collect();
+        let messages: Vec<_> = recipients
+            .iter()
+            .enumerate()
+            .map(|(i, recipient)| {
+                let lamports = 1_000_000 * (i as u64 + 1);
+                message(
+                    &[system_instruction::transfer(&payer, recipient, lamports)],
+                    &payer,
+                )
+            })
+            .collect();
+
+        let mut pool = ExecutorPool::new(executor(), 2);
+        let records = pool.simulate_many(SLOT, &messages, source(&accounts));
+        assert_eq!(records.len(), 5);
+        for (i, (record, recipient)) in records.iter().zip(&recipients).enumerate() {
+            let record = record.as_ref().unwrap();
+            assert_eq!(record.status, Ok(()));
+            assert_eq!(record.keys[1], *recipient);
+            assert_eq!(record.datas[1].lamports(), 1_000_000 * (i as u64 + 1));
+        }
+    }
+
+    #[test]
+    fn empty_and_single_message_batches() {
+        let payer = Pubkey::new_unique();
+        let accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        let mut pool = ExecutorPool::new(executor(), 4);
+
+        assert!(pool.simulate_many(SLOT, &[], source(&accounts)).is_empty());
+
+        let recipient = Pubkey::new_unique();
+        let ix = system_instruction::transfer(&payer, &recipient, 1_000_000);
+        let records = pool.simulate_many(SLOT, &[message(&[ix], &payer)], source(&accounts));
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].as_ref().unwrap().status, Ok(()));
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc, RwLock},
+};
+
+use solana_program_runtime::loaded_programs::{LoadedProgram, LoadedProgramType};
+use solana_sdk::{pubkey::Pubkey, slot_history::Slot};
+
+use super::{ForkGraph, MessageExecutor};
+
+#[derive(Debug, Clone, Default)]
+pub struct ProgramCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub insertions: u64,
+    pub evictions: u64,
+    pub prunes: u64, // entries dropped because they are orphaned, expired or outdated
+    pub compiled: usize, // number of compiled entries
+    pub compiled_size: usize, // bytes held by the compiled entries
+}
+
+// When each program was last loaded, in loads rather than slots as the same slot is usually
+// simulated over and over, see evict_programs
+#[derive(Debug, Default)]
+pub struct ProgramUses {
+    sequence: u64,
+    last_use: HashMap<Pubkey, u64>,
+}
+
+impl ProgramUses {
+    // The programs used by the same load share its sequence number
+    pub(crate) fn record<I: IntoIterator<Item = Pubkey>>(&mut self, program_ids: I) {
+        self.sequence += 1;
+        for program_id in program_ids {
+            self.last_use.insert(program_id, self.sequence);
+        }
+    }
+
+    // 0 for programs never loaded, e.g. deployed by a committed transaction
+    fn last_use(&self, program_id: &Pubkey) -> u64 {
+        self.last_use.get(program_id).copied().unwrap_or_default()
+    }
+}
+
+impl MessageExecutor {
+    // Drop the entries that are not reachable from `new_root` in `fork_graph`.
+    // The fork graph is only used for pruning so that extraction keeps following the working slot.
+    pub fn prune_program_cache(&mut self, fork_graph: ForkGraph, new_root: Slot) {
+        let new_root_epoch = self.epoch_schedule().get_epoch(new_root);
+        self.loaded_programs
+            .set_fork_graph(Arc::new(RwLock::new(fork_graph)));
+        self.loaded_programs.prune(new_root, new_root_epoch);
+        self.loaded_programs.fork_graph = None;
+    }
+
+    // Remove every version of the given programs and their load errors,
+    // e.g. after deploying a new ELF under the same id.
+    // Builtins cannot be loaded from accounts and are kept.
+    pub fn invalidate_programs<I: IntoIterator<Item = Pubkey>>(&mut self, program_ids: I) {
+        let program_ids: Vec<_> = program_ids
+            .into_iter()
+            .filter(|program_id| !self.builtin_programs.contains(program_id))
+            .collect();
+        for program_id in &program_ids {
+            self.program_load_errors.remove(program_id);
+            self.program_uses.last_use.remove(program_id);
+        }
+        self.loaded_programs
+            .remove_programs(program_ids.into_iter());
+    }
+
+    // Evict the least recently used compiled programs until at most `max_entries` compiled entries
+    // and `max_compiled_size` bytes are left, the least used first among programs last used by the
+    // same load. Like LoadedPrograms::sort_and_unload the entries are unloaded rather than removed,
+    // so tombstones are not affected.
+    pub fn evict_programs(&mut self, max_entries: usize, max_compiled_size: usize) {
+        let mut entries = self
+            .loaded_programs
+            .get_entries_sorted_by_tx_usage(true, true);
+        entries.sort_by_key(|(program_id, _)| self.program_uses.last_use(program_id));
+        let mut count = entries.len();
+        let mut size: usize = entries
+            .iter()
+            .map(|(_, program)| compiled_size(program))
+            .sum();
+
+        for (program_id, program) in entries {
+            if count <= max_entries && size <= max_compiled_size {
+                break;
+            }
+            count -= 1;
+            size = size.saturating_sub(compiled_size(&program));
+
+            let Some(unloaded) = program.to_unloaded() else {
+                continue;
+            };
+            // replenish adds the usage of the replaced entry to the new one
+            unloaded.tx_usage_counter.store(0, Ordering::Relaxed);
+            unloaded.ix_usage_counter.store(0, Ordering::Relaxed);
+            self.loaded_programs
+                .replenish(program_id, Arc::new(unloaded));
+            *self
+                .loaded_programs
+                .stats
+                .evictions
+                .entry(program_id)
+                .or_default() += 1;
+        }
+    }
+
+    pub fn program_cache_stats(&self) -> ProgramCacheStats {
+        let stats = &self.loaded_programs.stats;
+        let entries = self
+            .loaded_programs
+            .get_entries_sorted_by_tx_usage(true, true);
+
+        ProgramCacheStats {
+            hits: stats.hits.load(Ordering::Relaxed),
+            misses: stats.misses.load(Ordering::Relaxed),
+            insertions: stats.insertions.load(Ordering::Relaxed),
+            evictions: stats.evictions.values().sum(),
+            prunes: stats.prunes_orphan.load(Ordering::Relaxed)
+                + stats.prunes_environment.load(Ordering::Relaxed),
+            compiled: entries.len(),
+            compiled_size: entries
+                .iter()
+                .map(|(_, program)| compiled_size(program))
+                .sum(),
+        }
+    }
+}
+
+fn compiled_size(program: &LoadedProgram) -> usize {
+    match &program.program {
+        LoadedProgramType::LegacyV0(executable)
+        | LoadedProgramType::LegacyV1(executable)
+        | LoadedProgramType::Typed(executable) => executable.mem_size(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_sdk::{instruction::Instruction, pubkey::Pubkey, rent::Rent};
+
+    use super::super::{
+        system_account,
+        test_utils::{executor, message, run, NOOP_ELF},
+        upgradeable_program_accounts,
+    };
+
+    #[test]
+    fn evicts_the_least_recently_used_program_first() {
+        let mut sbf = executor();
+        let payer = Pubkey::new_unique();
+        let (busy, recent) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        for program_id in [busy, recent] {
+            let program =
+                upgradeable_program_accounts(&program_id, NOOP_ELF, None, 0, &Rent::default());
+            accounts.extend(program.unwrap());
+        }
+
+        let call = |program_id| {
+            message(
+                &[Instruction::new_with_bytes(program_id, &[], vec![])],
+                &payer,
+            )
+        };
+        for program_id in [busy, busy, busy, recent] {
+            let record = run(&mut sbf, &call(program_id), &accounts).unwrap();
+            assert_eq!(record.status, Ok(()));
+        }
+
+        // More used but less recently
+        sbf.evict_programs(1, usize::MAX);
+        let stats = sbf.program_cache_stats();
+        assert_eq!(stats.compiled, 1);
+        assert_eq!(stats.evictions, 1);
+        let compiled = sbf
+            .loaded_programs
+            .get_entries_sorted_by_tx_usage(true, true);
+        assert_eq!(compiled[0].0, recent);
+
+        // The unloaded program is compiled again on its next use, which makes the other one the
+        // least recently used
+        let record = run(&mut sbf, &call(busy), &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+        assert_eq!(sbf.program_cache_stats().compiled, 2);
+        sbf.evict_programs(1, usize::MAX);
+        let compiled = sbf
+            .loaded_programs
+            .get_entries_sorted_by_tx_usage(true, true);
+        assert_eq!(compiled[0].0, busy);
+    }
+}
@@ -0,0 +1,352 @@
+use anyhow::Error;
+use fehler::throws;
+use solana_program_runtime::loaded_programs::LoadedProgramsForTxBatch;
+use solana_sdk::{
+    account::{AccountSharedData, WritableAccount},
+    account_utils::StateMut,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    loader_v4::{self, LoaderV4State, LoaderV4Status},
+    pubkey::Pubkey,
+    rent::Rent,
+    slot_history::Slot,
+};
+
+use super::MessageExecutor;
+
+// The program and programdata accounts of an upgradeable loader (v3) program deployed at `slot`.
+// Note that the program only becomes visible after DELAY_VISIBILITY_SLOT_OFFSET slots.
+#[throws(Error)]
+pub fn upgradeable_program_accounts(
+    program_id: &Pubkey,
+    elf: &[u8],
+    upgrade_authority: Option<Pubkey>,
+    slot: Slot,
+    rent: &Rent,
+) -> [(Pubkey, AccountSharedData); 2] {
+    let (programdata_address, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+
+    let program_len = UpgradeableLoaderState::size_of_program();
+    let mut program = AccountSharedData::new(
+        rent.minimum_balance(program_len),
+        program_len,
+        &bpf_loader_upgradeable::id(),
+    );
+    program.set_state(&UpgradeableLoaderState::Program {
+        programdata_address,
+    })?;
+    program.set_executable(true);
+
+    let programdata_len = UpgradeableLoaderState::size_of_programdata(elf.len());
+    let mut programdata = AccountSharedData::new(
+        rent.minimum_balance(programdata_len),
+        programdata_len,
+        &bpf_loader_upgradeable::id(),
+    );
+    programdata.set_state(&UpgradeableLoaderState::ProgramData {
+        slot,
+        upgrade_authority_address: upgrade_authority,
+    })?;
+    programdata.data_as_mut_slice()[UpgradeableLoaderState::size_of_programdata_metadata()..]
+        .copy_from_slice(elf);
+
+    [(*program_id, program), (programdata_address, programdata)]
+}
+
+// A buffer holding `elf`, as written by the upgradeable loader before DeployWithMaxDataLen or Upgrade
+#[throws(Error)]
+pub fn upgradeable_buffer_account(
+    elf: &[u8],
+    authority: Option<Pubkey>,
+    rent: &Rent,
+) -> AccountSharedData {
+    let len = UpgradeableLoaderState::size_of_buffer(elf.len());
+    let mut buffer = AccountSharedData::new(
+        rent.minimum_balance(len),
+        len,
+        &bpf_loader_upgradeable::id(),
+    );
+    buffer.set_state(&UpgradeableLoaderState::Buffer {
+        authority_address: authority,
+    })?;
+    buffer.data_as_mut_slice()[UpgradeableLoaderState::size_of_buffer_metadata()..]
+        .copy_from_slice(elf);
+    buffer
+}
+
+// A loader-v4 program deployed at `slot`
+pub fn loader_v4_program_account(
+    elf: &[u8],
+    authority: &Pubkey,
+    slot: Slot,
+    rent: &Rent,
+) -> AccountSharedData {
+    let offset = LoaderV4State::program_data_offset();
+    let len = offset + elf.len();
+    let mut program = AccountSharedData::new(rent.minimum_balance(len), len, &loader_v4::id());
+
+    let state = LoaderV4State {
+        slot,
+        authority_address: *authority,
+        status: LoaderV4Status::Deployed,
+    };
+    // LoaderV4State is repr(C) without padding and stored as is in front of the ELF, see
+    // solana_loader_v4_program::get_state
+    let state = unsafe {
+        std::slice::from_raw_parts((&state as *const LoaderV4State).cast::<u8>(), offset)
+    };
+    let data = program.data_as_mut_slice();
+    data[..offset].copy_from_slice(state);
+    data[offset..].copy_from_slice(elf);
+    program
+}
+
+impl MessageExecutor {
+    // Bank::commit_transactions for the program cache: make the programs deployed, upgraded or closed
+    // by a transaction visible to the following ones.
+    pub fn commit_programs(&mut self, programs_modified: &LoadedProgramsForTxBatch) {
+        self.loaded_programs.merge(programs_modified);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_sdk::{
+        account::{AccountSharedData, ReadableAccount},
+        account_utils::StateMut,
+        bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+        feature_set::{
+            delay_visibility_of_program_deployment, enable_program_runtime_v2_and_loader_v4,
+        },
+        instruction::{AccountMeta, Instruction, InstructionError},
+        loader_v4::{LoaderV4State, LoaderV4Status},
+        pubkey::Pubkey,
+        rent::Rent,
+        transaction::TransactionError,
+    };
+
+    use super::{
+        super::{
+            system_account,
+            test_utils::{
+                commit, executor, feature_set, message, run_at, LOADER_V4_ELF, NOOP_ELF, SLOT,
+            },
+            MessageExecutor, ProgramLoadError,
+        },
+        loader_v4_program_account, upgradeable_buffer_account, upgradeable_program_accounts,
+    };
+
+    #[test]
+    fn deployed_program_is_visible_from_the_next_slot() {
+        // Without this feature, which is not in FEATURES, deployed programs are visible at once
+        let mut features = feature_set();
+        features.activate(&delay_visibility_of_program_deployment::id(), 0);
+        let mut sbf = MessageExecutor::new_with_feature_set(features).unwrap();
+        sbf.set_synthetic_sysvars(SLOT, 0);
+        let (payer, buffer, program_id) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let rent = Rent::default();
+        let mut accounts = HashMap::from([
+            (payer, system_account(10_000_000_000)),
+            (
+                buffer,
+                upgradeable_buffer_account(NOOP_ELF, Some(payer), &rent).unwrap(),
+            ),
+        ]);
+
+        let deploy = bpf_loader_upgradeable::deploy_with_max_program_len(
+            &payer,
+            &program_id,
+            &buffer,
+            &payer,
+            rent.minimum_balance(bpf_loader_upgradeable::UpgradeableLoaderState::size_of_program()),
+            NOOP_ELF.len() * 2,
+        )
+        .unwrap();
+        let record = run_at(&mut sbf, SLOT, &message(&deploy, &payer), &accounts).unwrap();
+        commit(&mut sbf, &record, &mut accounts);
+
+        // Like on the cluster, the program cannot be invoked in the slot it was deployed in
+        let invoke = message(
+            &[Instruction::new_with_bytes(program_id, &[], vec![])],
+            &payer,
+        );
+        let record = run_at(&mut sbf, SLOT, &invoke, &accounts).unwrap();
+        assert!(record.status.is_err());
+        assert_eq!(
+            record.tombstones,
+            vec![(program_id, ProgramLoadError::DelayVisibility)]
+        );
+
+        sbf.set_synthetic_sysvars(SLOT + 1, 0);
+        let record = run_at(&mut sbf, SLOT + 1, &invoke, &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+        assert!(record.tombstones.is_empty());
+    }
+
+    #[test]
+    fn closed_program_is_a_tombstone() {
+        let mut sbf = executor();
+        let (payer, program_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let program =
+            upgradeable_program_accounts(&program_id, NOOP_ELF, Some(payer), 0, &Rent::default())
+                .unwrap();
+        let programdata = program[1].0;
+        let mut accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        accounts.extend(program);
+
+        let invoke = message(
+            &[Instruction::new_with_bytes(program_id, &[], vec![])],
+            &payer,
+        );
+        let record = run_at(&mut sbf, SLOT, &invoke, &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+
+        let close = bpf_loader_upgradeable::close_any(
+            &programdata,
+            &payer,
+            Some(&payer),
+            Some(&program_id),
+        );
+        let record = run_at(&mut sbf, SLOT, &message(&[close], &payer), &accounts).unwrap();
+        commit(&mut sbf, &record, &mut accounts);
+
+        sbf.set_synthetic_sysvars(SLOT + 1, 0);
+        let record = run_at(&mut sbf, SLOT + 1, &invoke, &accounts).unwrap();
+        assert!(record.status.is_err());
+        assert_eq!(
+            record.tombstones,
+            vec![(program_id, ProgramLoadError::Closed)]
+        );
+    }
+
+    #[test]
+    fn upgrade_and_set_authority() {
+        let mut sbf = executor();
+        let (payer, authority, buffer, program_id) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let rent = Rent::default();
+        let program =
+            upgradeable_program_accounts(&program_id, NOOP_ELF, Some(payer), 0, &rent).unwrap();
+        let programdata = program[0].1.state().map(|state| match state {
+            UpgradeableLoaderState::Program {
+                programdata_address,
+            } => programdata_address,
+            _ => panic!("expected a program account"),
+        });
+        let programdata = programdata.unwrap();
+        let mut accounts = HashMap::from([
+            (payer, system_account(1_000_000_000)),
+            (authority, system_account(1_000_000_000)),
+            (
+                buffer,
+                upgradeable_buffer_account(NOOP_ELF, Some(authority), &rent).unwrap(),
+            ),
+        ]);
+        accounts.extend(program);
+        let upgrade_authority = |accounts: &HashMap<Pubkey, AccountSharedData>| match accounts
+            [&programdata]
+            .state()
+            .unwrap()
+        {
+            UpgradeableLoaderState::ProgramData {
+                slot,
+                upgrade_authority_address,
+            } => (slot, upgrade_authority_address),
+            _ => panic!("expected a programdata account"),
+        };
+
+        let set_authority =
+            bpf_loader_upgradeable::set_upgrade_authority(&program_id, &payer, Some(&authority));
+        let record = run_at(
+            &mut sbf,
+            SLOT,
+            &message(&[set_authority], &payer),
+            &accounts,
+        )
+        .unwrap();
+        commit(&mut sbf, &record, &mut accounts);
+        assert_eq!(upgrade_authority(&accounts), (0, Some(authority)));
+
+        // The former authority cannot upgrade anymore
+        let mut upgrade = bpf_loader_upgradeable::upgrade(&program_id, &buffer, &payer, &payer);
+        let record = run_at(
+            &mut sbf,
+            SLOT,
+            &message(&[upgrade.clone()], &payer),
+            &accounts,
+        )
+        .unwrap();
+        assert_eq!(
+            record.status,
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::IncorrectAuthority
+            ))
+        );
+
+        upgrade.accounts[6] = AccountMeta::new_readonly(authority, true);
+        let record = run_at(&mut sbf, SLOT, &message(&[upgrade], &payer), &accounts).unwrap();
+        commit(&mut sbf, &record, &mut accounts);
+        assert_eq!(upgrade_authority(&accounts), (SLOT, Some(authority)));
+        assert_eq!(accounts[&buffer].lamports(), 0);
+
+        let invoke = message(
+            &[Instruction::new_with_bytes(program_id, &[], vec![])],
+            &payer,
+        );
+        sbf.set_synthetic_sysvars(SLOT + 1, 0);
+        let record = run_at(&mut sbf, SLOT + 1, &invoke, &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+    }
+
+    #[test]
+    fn loader_v4_program_is_invoked() {
+        let mut features = feature_set();
+        features.activate(&enable_program_runtime_v2_and_loader_v4::id(), 0);
+        let mut sbf = MessageExecutor::new_with_feature_set(features).unwrap();
+        sbf.set_synthetic_sysvars(SLOT, 0);
+        let (payer, authority, program_id) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let program = loader_v4_program_account(LOADER_V4_ELF, &authority, 0, &Rent::default());
+        assert_eq!(
+            solana_loader_v4_program::get_state(program.data()).unwrap(),
+            &LoaderV4State {
+                slot: 0,
+                authority_address: authority,
+                status: LoaderV4Status::Deployed,
+            }
+        );
+        let accounts = HashMap::from([
+            (payer, system_account(1_000_000_000)),
+            (program_id, program),
+        ]);
+
+        // The program ran to its own error
+        let invoke = message(
+            &[Instruction::new_with_bytes(program_id, &[], vec![])],
+            &payer,
+        );
+        let record = run_at(&mut sbf, SLOT, &invoke, &accounts).unwrap();
+        assert!(record.tombstones.is_empty());
+        assert_eq!(
+            record.status,
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(42)
+            ))
+        );
+    }
+}
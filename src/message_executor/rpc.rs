@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_sdk::{pubkey::Pubkey, transaction::TransactionError};
+use solana_transaction_status::{
+    UiCompiledInstruction, UiInnerInstructions, UiInstruction, UiTransactionReturnData,
+};
+
+use super::ExecutionRecord;
+
+// The `value` of a simulateTransaction response, including the innerInstructions of newer RPC nodes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSimulateTransactionResult {
+    pub err: Option<TransactionError>,
+    pub logs: Option<Vec<String>>,
+    pub accounts: Option<Vec<Option<UiAccount>>>,
+    pub units_consumed: Option<u64>,
+    pub return_data: Option<UiTransactionReturnData>,
+    pub inner_instructions: Option<Vec<UiInnerInstructions>>,
+}
+
+impl RpcSimulateTransactionResult {
+    // A transaction that failed before execution, e.g. because the fee payer cannot pay the fee
+    pub fn from_error(err: TransactionError) -> Self {
+        Self {
+            err: Some(err),
+            ..Self::default()
+        }
+    }
+}
+
+impl ExecutionRecord {
+    // `addresses` are the accounts to return, in base64, like the `accounts` config of simulateTransaction.
+    // Addresses that are not part of the message are None.
+    pub fn to_rpc_simulate_result(
+        &self,
+        addresses: Option<&[Pubkey]>,
+    ) -> RpcSimulateTransactionResult {
+        let accounts = addresses.map(|addresses| {
+            addresses
+                .iter()
+                .map(|address| {
+                    let index = self.keys.iter().position(|key| key == address)?;
+                    let account = self.datas.get(index)?;
+                    Some(UiAccount::encode(
+                        address,
+                        account,
+                        UiAccountEncoding::Base64,
+                        None,
+                        None,
+                    ))
+                })
+                .collect()
+        });
+
+        let inner_instructions = self
+            .inner_instructions
+            .iter()
+            .filter(|inner_instructions| !inner_instructions.instructions.is_empty())
+            .map(|inner_instructions| UiInnerInstructions {
+                index: inner_instructions.index,
+                instructions: inner_instructions
+                    .instructions
+                    .iter()
+                    .map(|inner| {
+                        UiInstruction::Compiled(UiCompiledInstruction {
+                            program_id_index: inner.instruction.program_id_index,
+                            accounts: inner.instruction.accounts.clone(),
+                            data: bs58::encode(&inner.instruction.data).into_string(),
+                            stack_height: Some(inner.stack_height as u32),
+                        })
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        RpcSimulateTransactionResult {
+            err: self.status.clone().err(),
+            logs: Some(self.logs.concat()),
+            accounts,
+            units_consumed: Some(self.cu),
+            return_data: self.return_data.clone().map(Into::into),
+            inner_instructions: Some(inner_instructions),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_account_decoder::UiAccountData;
+    use solana_sdk::{
+        instruction::CompiledInstruction, pubkey::Pubkey, system_instruction,
+        transaction::TransactionError, transaction_context::TransactionReturnData,
+    };
+    use solana_transaction_status::UiInstruction;
+
+    use super::{
+        super::{
+            system_account,
+            test_utils::{executor, message, run},
+            InnerInstruction, InnerInstructions,
+        },
+        RpcSimulateTransactionResult,
+    };
+
+    #[test]
+    fn simulate_result_of_a_record() {
+        let mut sbf = executor();
+        let (payer, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let accounts = HashMap::from([(payer, system_account(1_000_000_000))]);
+        let ix = system_instruction::transfer(&payer, &recipient, 1_000_000);
+        let mut record = run(&mut sbf, &message(&[ix], &payer), &accounts).unwrap();
+        record.return_data = Some(TransactionReturnData {
+            program_id: recipient,
+            data: vec![1, 2, 3],
+        });
+        record.inner_instructions = vec![
+            InnerInstructions {
+                index: 0,
+                compute_units_consumed: Some(150),
+                instructions: vec![InnerInstruction {
+                    instruction: CompiledInstruction::new_from_raw_parts(2, vec![4], vec![0, 1]),
+                    stack_height: 2,
+                    compute_units_consumed: None,
+                }],
+            },
+            InnerInstructions {
+                index: 1,
+                compute_units_consumed: None,
+                instructions: vec![],
+            },
+        ];
+
+        let missing = Pubkey::new_unique();
+        let result = record.to_rpc_simulate_result(Some(&[recipient, missing]));
+        assert_eq!(result.err, None);
+        assert_eq!(result.logs, Some(record.logs.concat()));
+        assert_eq!(result.units_consumed, Some(record.cu));
+
+        let accounts = result.accounts.unwrap();
+        let account = accounts[0].as_ref().unwrap();
+        assert_eq!(account.lamports, 1_000_000);
+        assert!(matches!(account.data, UiAccountData::Binary(_, _)));
+        assert!(accounts[1].is_none());
+
+        let return_data = result.return_data.unwrap();
+        assert_eq!(return_data.program_id, recipient.to_string());
+
+        // Top-level instructions without CPIs are left out, like RPC does
+        let inner_instructions = result.inner_instructions.unwrap();
+        assert_eq!(inner_instructions.len(), 1);
+        assert_eq!(inner_instructions[0].index, 0);
+        let UiInstruction::Compiled(inner) = &inner_instructions[0].instructions[0] else {
+            panic!("expected a compiled instruction");
+        };
+        assert_eq!(inner.program_id_index, 2);
+        assert_eq!(inner.accounts, vec![0, 1]);
+        assert_eq!(inner.data, bs58::encode([4]).into_string());
+        assert_eq!(inner.stack_height, Some(2));
+
+        // Accounts are only returned when asked for
+        assert_eq!(record.to_rpc_simulate_result(None).accounts, None);
+        assert_eq!(
+            record.to_rpc_simulate_result(Some(&[])).accounts,
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn simulate_result_of_an_error() {
+        let result = RpcSimulateTransactionResult::from_error(TransactionError::AccountNotFound);
+        assert_eq!(result.err, Some(TransactionError::AccountNotFound));
+        assert_eq!(result.logs, None);
+        assert_eq!(result.accounts, None);
+        assert_eq!(result.units_consumed, None);
+    }
+}
@@ -0,0 +1,234 @@
+#![allow(deprecated)] // Fees and RecentBlockhashes are still part of the sysvar cache
+
+use fehler::{throw, throws};
+use solana_accounts_db::rent_collector::RENT_EXEMPT_RENT_EPOCH;
+use solana_sdk::{
+    account::{create_account_shared_data_with_fields, AccountSharedData, ReadableAccount},
+    clock::{Clock, Slot, UnixTimestamp, DEFAULT_MS_PER_SLOT},
+    epoch_rewards::EpochRewards,
+    fee_calculator::FeeCalculator,
+    hash::hashv,
+    pubkey::Pubkey,
+    rent::Rent,
+    slot_hashes::{self, SlotHashes},
+    stake_history::StakeHistory,
+    sysvar::{
+        self,
+        fees::Fees,
+        last_restart_slot::LastRestartSlot,
+        recent_blockhashes::{self, IterItem, RecentBlockhashes},
+        Sysvar,
+    },
+};
+
+use super::{AccountLoadError, AccountSource, MessageExecutor};
+
+impl MessageExecutor {
+    // Replace every sysvar in the cache with the one found through `g`, e.g. an RPC client or a fixture.
+    // Sysvars that do not exist are left unset, the first failure of `g` is returned.
+    #[throws(AccountLoadError)]
+    pub fn fill_sysvar_cache<G>(&mut self, g: &mut G)
+    where
+        G: AccountSource,
+    {
+        let mut failure = None;
+        self.sysvar_cache_mut().reset();
+        self.sysvar_cache_mut()
+            .fill_missing_entries(|pubkey, set_sysvar| {
+                if failure.is_some() {
+                    return;
+                }
+                match g.get_account(pubkey) {
+                    Ok(Some(account)) => set_sysvar(account.data()),
+                    Ok(None) => {}
+                    Err(e) => failure = Some(e),
+                }
+            });
+        if let Some(e) = failure {
+            throw!(e);
+        }
+    }
+
+    // Build a coherent set of sysvars for `slot` when no cluster is at hand.
+    // The epoch schedule in the cache is kept if present, hashes are derived from slot numbers.
+    pub fn set_synthetic_sysvars(&mut self, slot: Slot, unix_timestamp: UnixTimestamp) {
+        let epoch_schedule = self.epoch_schedule();
+        let lamports_per_signature = self.fee_structure().lamports_per_signature;
+
+        let epoch = epoch_schedule.get_epoch(slot);
+        let slots_into_epoch = slot.saturating_sub(epoch_schedule.get_first_slot_in_epoch(epoch));
+        let clock = Clock {
+            slot,
+            epoch_start_timestamp: unix_timestamp
+                .saturating_sub((slots_into_epoch * DEFAULT_MS_PER_SLOT / 1000) as UnixTimestamp),
+            epoch,
+            leader_schedule_epoch: epoch_schedule.get_leader_schedule_epoch(slot),
+            unix_timestamp,
+        };
+
+        let slot_hash = |slot: Slot| hashv(&[b"slot", &slot.to_le_bytes()]);
+        let ancestors: Vec<_> = (0..slot).rev().take(slot_hashes::MAX_ENTRIES).collect();
+        let slot_hashes = SlotHashes::new(
+            &ancestors
+                .iter()
+                .map(|&slot| (slot, slot_hash(slot)))
+                .collect::<Vec<_>>(),
+        );
+        let blockhashes: Vec<_> = ancestors
+            .iter()
+            .take(recent_blockhashes::MAX_ENTRIES)
+            .map(|&slot| (slot, slot_hash(slot)))
+            .collect();
+        let recent_blockhashes = blockhashes
+            .iter()
+            .map(|(slot, hash)| IterItem(*slot, hash, lamports_per_signature))
+            .collect::<RecentBlockhashes>();
+
+        let sysvar_cache = self.sysvar_cache_mut();
+        sysvar_cache.reset();
+        sysvar_cache.set_clock(clock);
+        sysvar_cache.set_epoch_schedule(epoch_schedule);
+        sysvar_cache.set_epoch_rewards(EpochRewards::default());
+        sysvar_cache.set_fees(Fees::new(&FeeCalculator::new(lamports_per_signature)));
+        sysvar_cache.set_rent(Rent::default());
+        sysvar_cache.set_slot_hashes(slot_hashes);
+        sysvar_cache.set_recent_blockhashes(recent_blockhashes);
+        sysvar_cache.set_stake_history(StakeHistory::default());
+        sysvar_cache.set_last_restart_slot(LastRestartSlot::default());
+    }
+
+    // The account of a sysvar in the cache, for account sources that lack sysvar accounts
+    pub fn sysvar_account(&self, pubkey: &Pubkey) -> Option<AccountSharedData> {
+        let rent = self.rent();
+        let cache = self.sysvar_cache();
+        let account = if sysvar::clock::check_id(pubkey) {
+            to_account(&*cache.get_clock().ok()?, &rent)
+        } else if sysvar::epoch_rewards::check_id(pubkey) {
+            to_account(&*cache.get_epoch_rewards().ok()?, &rent)
+        } else if sysvar::epoch_schedule::check_id(pubkey) {
+            to_account(&*cache.get_epoch_schedule().ok()?, &rent)
+        } else if sysvar::fees::check_id(pubkey) {
+            to_account(&*cache.get_fees().ok()?, &rent)
+        } else if sysvar::last_restart_slot::check_id(pubkey) {
+            to_account(&*cache.get_last_restart_slot().ok()?, &rent)
+        } else if sysvar::recent_blockhashes::check_id(pubkey) {
+            to_account(&*cache.get_recent_blockhashes().ok()?, &rent)
+        } else if sysvar::rent::check_id(pubkey) {
+            to_account(&*cache.get_rent().ok()?, &rent)
+        } else if sysvar::slot_hashes::check_id(pubkey) {
+            to_account(&*cache.get_slot_hashes().ok()?, &rent)
+        } else if sysvar::stake_history::check_id(pubkey) {
+            to_account(&*cache.get_stake_history().ok()?, &rent)
+        } else {
+            return None;
+        };
+        Some(account)
+    }
+}
+
+fn to_account<S: Sysvar>(sysvar: &S, rent: &Rent) -> AccountSharedData {
+    create_account_shared_data_with_fields(
+        sysvar,
+        (
+            rent.minimum_balance(S::size_of()).max(1),
+            RENT_EXEMPT_RENT_EPOCH,
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::{
+        account::{from_account, AccountSharedData},
+        clock::Clock,
+        pubkey::Pubkey,
+        slot_hashes::{self, SlotHashes},
+        sysvar::{self, recent_blockhashes},
+    };
+
+    use super::{
+        super::{
+            test_utils::{executor, SLOT},
+            AccountLoadError, AccountSource,
+        },
+        MessageExecutor,
+    };
+
+    // Fails on the clock sysvar and serves the other sysvars of `sbf`
+    struct FailingClock<'a>(&'a MessageExecutor);
+
+    impl AccountSource for FailingClock<'_> {
+        fn get_account(
+            &mut self,
+            pubkey: &Pubkey,
+        ) -> Result<Option<AccountSharedData>, AccountLoadError> {
+            if sysvar::clock::check_id(pubkey) {
+                return Err(AccountLoadError::SourceFailure {
+                    pubkey: *pubkey,
+                    reason: "timed out".to_string(),
+                });
+            }
+            Ok(self.0.sysvar_account(pubkey))
+        }
+    }
+
+    #[test]
+    fn synthetic_sysvars_follow_the_slot() {
+        let mut sbf = executor();
+        sbf.set_synthetic_sysvars(SLOT, 1_700_000_000);
+        let cache = sbf.sysvar_cache();
+
+        let clock = cache.get_clock().unwrap();
+        assert_eq!(clock.slot, SLOT);
+        assert_eq!(clock.epoch, sbf.epoch_schedule().get_epoch(SLOT));
+        assert_eq!(clock.unix_timestamp, 1_700_000_000);
+        assert!(clock.epoch_start_timestamp <= clock.unix_timestamp);
+
+        // The most recent ancestors first
+        let slot_hashes = cache.get_slot_hashes().unwrap();
+        assert_eq!(slot_hashes.len(), slot_hashes::MAX_ENTRIES);
+        assert_eq!(slot_hashes.first().map(|(slot, _)| *slot), Some(SLOT - 1));
+        let blockhashes = cache.get_recent_blockhashes().unwrap();
+        assert_eq!(blockhashes.len(), recent_blockhashes::MAX_ENTRIES);
+        assert_eq!(
+            blockhashes[0].blockhash,
+            slot_hashes.get(&(SLOT - 1)).copied().unwrap()
+        );
+
+        // Accounts for sources that lack them
+        let account = sbf.sysvar_account(&sysvar::clock::id()).unwrap();
+        assert_eq!(from_account::<Clock, _>(&account).unwrap(), *clock);
+        let account = sbf.sysvar_account(&sysvar::slot_hashes::id()).unwrap();
+        assert_eq!(
+            from_account::<SlotHashes, _>(&account).unwrap(),
+            *slot_hashes
+        );
+        assert!(sbf.sysvar_account(&Pubkey::new_unique()).is_none());
+    }
+
+    #[test]
+    fn fill_sysvar_cache_from_a_source() {
+        let mut cluster = executor();
+        cluster.set_synthetic_sysvars(5_000, 0);
+
+        // Sysvars the source does not have are left unset
+        let mut sbf = executor();
+        sbf.fill_sysvar_cache(&mut |pubkey: &Pubkey| {
+            (!sysvar::stake_history::check_id(pubkey))
+                .then(|| cluster.sysvar_account(pubkey))
+                .flatten()
+        })
+        .unwrap();
+        assert_eq!(sbf.sysvar_cache().get_clock().unwrap().slot, 5_000);
+        assert_eq!(
+            sbf.sysvar_cache().get_slot_hashes().unwrap(),
+            cluster.sysvar_cache().get_slot_hashes().unwrap()
+        );
+        assert!(sbf.sysvar_cache().get_stake_history().is_err());
+
+        let error = sbf
+            .fill_sysvar_cache(&mut FailingClock(&cluster))
+            .unwrap_err();
+        assert_eq!(error.pubkey(), &sysvar::clock::id());
+    }
+}
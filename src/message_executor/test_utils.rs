@@ -0,0 +1,95 @@
+// Helpers for the tests of the message executor
+use std::collections::HashMap;
+
+use anyhow::Error;
+use solana_runtime::builtins::BUILTINS;
+use solana_sdk::{
+    account::AccountSharedData,
+    feature_set::FeatureSet,
+    instruction::Instruction,
+    message::{LegacyMessage, Message, SanitizedMessage},
+    pubkey::Pubkey,
+    slot_history::Slot,
+};
+
+use super::{native_program_account, ExecutionRecord, MessageExecutor, FEATURES};
+
+pub(crate) const SLOT: Slot = 1000;
+
+// noop_aligned.so of the solana-bpf-loader-program tests, returns success
+pub(crate) const NOOP_ELF: &[u8] = include_bytes!("../../test_elfs/noop.so");
+
+// rodata_section.so of the solana-loader-v4-program tests, an SBPFv2 program failing with Custom(42)
+pub(crate) const LOADER_V4_ELF: &[u8] = include_bytes!("../../test_elfs/rodata_section.so");
+
+// FEATURES, all active since slot 0
+pub(crate) fn feature_set() -> FeatureSet {
+    let mut features = FeatureSet::default();
+    for feature in FEATURES {
+        features.activate(feature, 0);
+    }
+    features
+}
+
+// An executor with the default features and synthetic sysvars at SLOT
+pub(crate) fn executor() -> MessageExecutor {
+    let mut sbf = MessageExecutor::new(FEATURES).unwrap();
+    sbf.set_synthetic_sysvars(SLOT, 0);
+    sbf
+}
+
+pub(crate) fn message(instructions: &[Instruction], payer: &Pubkey) -> SanitizedMessage {
+    SanitizedMessage::Legacy(LegacyMessage::new(Message::new(instructions, Some(payer))))
+}
+
+// Serves `accounts`, the builtins as in genesis and every other account as missing
+pub(crate) fn source(
+    accounts: &HashMap<Pubkey, AccountSharedData>,
+) -> impl FnMut(&Pubkey) -> Option<AccountSharedData> + Clone + '_ {
+    |key: &Pubkey| {
+        accounts.get(key).cloned().or_else(|| {
+            BUILTINS
+                .iter()
+                .find(|builtin| builtin.program_id == *key)
+                .map(|builtin| native_program_account(builtin.name))
+        })
+    }
+}
+
+// Loads and processes `message` at SLOT
+pub(crate) fn run(
+    sbf: &mut MessageExecutor,
+    message: &SanitizedMessage,
+    accounts: &HashMap<Pubkey, AccountSharedData>,
+) -> Result<ExecutionRecord, Error> {
+    run_at(sbf, SLOT, message, accounts)
+}
+
+pub(crate) fn run_at(
+    sbf: &mut MessageExecutor,
+    slot: Slot,
+    message: &SanitizedMessage,
+    accounts: &HashMap<Pubkey, AccountSharedData>,
+) -> Result<ExecutionRecord, Error> {
+    let working_slot = sbf.working_slot(slot);
+    let (loaded_transaction, loaded_programs) =
+        sbf.loader(source(accounts)).load(&working_slot, message)?;
+    sbf.process(slot, message, loaded_transaction, &loaded_programs)
+}
+
+// Commits the accounts and programs of a successful record, like the bank does
+pub(crate) fn commit(
+    sbf: &mut MessageExecutor,
+    record: &ExecutionRecord,
+    accounts: &mut HashMap<Pubkey, AccountSharedData>,
+) {
+    assert_eq!(record.status, Ok(()));
+    accounts.extend(
+        record
+            .keys
+            .iter()
+            .copied()
+            .zip(record.datas.iter().cloned()),
+    );
+    sbf.commit_programs(&record.programs_modified);
+}
@@ -0,0 +1,134 @@
+use solana_accounts_db::account_rent_state::RentState;
+use solana_sdk::{
+    account::{AccountSharedData, ReadableAccount},
+    incinerator,
+    message::SanitizedMessage,
+    native_loader,
+    pubkey::Pubkey,
+    rent::Rent,
+    transaction::TransactionError,
+    transaction_context::{IndexOfAccount, TransactionContext},
+};
+
+use super::MessageExecutor;
+
+pub(crate) struct TransactionAccountStateInfo {
+    rent_state: Option<RentState>, // None: readonly account
+}
+
+// Bank::get_transaction_account_state_info and friends
+impl MessageExecutor {
+    pub(crate) fn get_transaction_account_state_info(
+        rent: &Rent,
+        transaction_context: &TransactionContext,
+        message: &SanitizedMessage,
+    ) -> Vec<TransactionAccountStateInfo> {
+        (0..message.account_keys().len())
+            .map(|i| {
+                let rent_state = if message.is_writable(i) {
+                    transaction_context
+                        .get_account_at_index(i as IndexOfAccount)
+                        .ok()
+                        .map(|account| account.borrow())
+                        // Native programs appear to be RentPaying because they carry low lamport
+                        // balances. The bank never loads them as writable, but a simulated message
+                        // can mark them so: they are skipped like readonly accounts.
+                        .filter(|account| !native_loader::check_id(account.owner()))
+                        .map(|account| RentState::from_account(&account, rent))
+                } else {
+                    None
+                };
+                TransactionAccountStateInfo { rent_state }
+            })
+            .collect()
+    }
+
+    pub(crate) fn verify_transaction_account_state_changes(
+        pre_state_infos: &[TransactionAccountStateInfo],
+        post_state_infos: &[TransactionAccountStateInfo],
+        transaction_context: &TransactionContext,
+    ) -> Result<(), TransactionError> {
+        for (i, (pre_state_info, post_state_info)) in
+            pre_state_infos.iter().zip(post_state_infos).enumerate()
+        {
+            if let Some((pre_rent_state, post_rent_state)) = pre_state_info
+                .rent_state
+                .as_ref()
+                .zip(post_state_info.rent_state.as_ref())
+            {
+                let address = transaction_context
+                    .get_key_of_account_at_index(i as IndexOfAccount)
+                    .map_err(|_| TransactionError::InvalidAccountIndex)?;
+                check_rent_state(
+                    pre_rent_state,
+                    post_rent_state,
+                    address,
+                    i as IndexOfAccount,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // The rent state part of Accounts::validate_fee_payer
+    pub(crate) fn check_fee_payer_rent_state(
+        rent: &Rent,
+        payer_address: &Pubkey,
+        pre_payer_account: &AccountSharedData,
+        post_payer_account: &AccountSharedData,
+        payer_index: IndexOfAccount,
+    ) -> Result<(), TransactionError> {
+        check_rent_state(
+            &RentState::from_account(pre_payer_account, rent),
+            &RentState::from_account(post_payer_account, rent),
+            payer_address,
+            payer_index,
+        )
+    }
+}
+
+// account_rent_state::check_rent_state_with_account
+fn check_rent_state(
+    pre_rent_state: &RentState,
+    post_rent_state: &RentState,
+    address: &Pubkey,
+    account_index: IndexOfAccount,
+) -> Result<(), TransactionError> {
+    if !incinerator::check_id(address) && !post_rent_state.transition_allowed_from(pre_rent_state) {
+        let account_index = account_index as u8;
+        Err(TransactionError::InsufficientFundsForRent { account_index })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey, system_instruction};
+
+    use super::super::{
+        native_program_account, system_account,
+        test_utils::{executor, message, run},
+    };
+
+    #[test]
+    fn writable_native_program_is_skipped() {
+        let mut sbf = executor();
+        let (payer, recipient, native) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let accounts = HashMap::from([
+            (payer, system_account(1_000_000_000)),
+            (native, native_program_account("native")),
+        ]);
+
+        let mut ix = system_instruction::transfer(&payer, &recipient, 1_000_000);
+        ix.accounts.push(AccountMeta::new(native, false));
+        let record = run(&mut sbf, &message(&[ix], &payer), &accounts).unwrap();
+        assert_eq!(record.status, Ok(()));
+    }
+}
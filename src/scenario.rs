@@ -0,0 +1,387 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use fehler::{throw, throws};
+use serde::Deserialize;
+use solana_accounts_db::rent_collector::RENT_EXEMPT_RENT_EPOCH;
+use solana_bpf_simulator::ExecutionRecord;
+use solana_sdk::{
+    account::{Account, AccountSharedData, ReadableAccount},
+    instruction::{AccountMeta, Instruction},
+    message::{LegacyMessage, Message, SanitizedMessage},
+    pubkey::Pubkey,
+    system_program,
+    transaction::TransactionError,
+};
+
+// A transaction to simulate together with the programs and accounts it runs against,
+// in TOML, JSON or YAML depending on the file extension
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub fee_payer: Option<Address>,
+    pub instructions: Vec<ScenarioInstruction>,
+    #[serde(default)]
+    pub programs: HashMap<Address, PathBuf>, // ELF files relative to the scenario file
+    #[serde(default)]
+    pub accounts: HashMap<Address, ScenarioAccount>,
+    #[serde(default)]
+    pub expect: Option<Expect>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioInstruction {
+    pub program_id: Address,
+    #[serde(default)]
+    pub accounts: Vec<ScenarioAccountMeta>,
+    #[serde(default)]
+    pub data: String, // base58 string
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioAccountMeta {
+    pub pubkey: Address,
+    #[serde(default)]
+    pub signer: bool,
+    #[serde(default)]
+    pub writable: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioAccount {
+    pub lamports: u64,
+    #[serde(default = "system_program_address")]
+    pub owner: Address,
+    #[serde(default)]
+    pub data: String, // base64 string
+    #[serde(default)]
+    pub executable: bool,
+    #[serde(default = "rent_exempt_rent_epoch")]
+    pub rent_epoch: u64,
+}
+
+// Checked against the execution, every field is optional
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    pub error: Option<String>, // a substring of the error ignoring case, success is expected if not given
+    #[serde(default)]
+    pub logs: Vec<String>, // substrings that must each appear in a log line
+    pub max_compute_units: Option<u64>,
+    #[serde(default)]
+    pub accounts: HashMap<Address, ExpectedAccount>, // after the execution
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedAccount {
+    pub lamports: Option<u64>,
+    pub owner: Option<Address>,
+    pub data: Option<String>, // base64 string
+}
+
+// A base58 pubkey, Pubkey itself deserializes from bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Address(pub Pubkey);
+
+impl TryFrom<String> for Address {
+    type Error = Error;
+
+    #[throws(Error)]
+    fn try_from(s: String) -> Self {
+        Address(s.parse().map_err(|e| anyhow!("{}: {}", s, e))?)
+    }
+}
+
+fn system_program_address() -> Address {
+    Address(system_program::id())
+}
+
+fn rent_exempt_rent_epoch() -> u64 {
+    RENT_EXEMPT_RENT_EPOCH
+}
+
+impl Scenario {
+    #[throws(Error)]
+    pub fn load(path: &Path) -> Self {
+        let text = fs::read_to_string(path)?;
+        let mut scenario: Scenario = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&text)?,
+            Some("json") => serde_json::from_str(&text)?,
+            Some("yaml" | "yml") => serde_yaml::from_str(&text)?,
+            _ => throw!(anyhow!(
+                "unknown scenario format {}, expected .toml, .json or .yaml",
+                path.display()
+            )),
+        };
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for program in scenario.programs.values_mut() {
+            *program = dir.join(&*program);
+        }
+        scenario
+    }
+
+    #[throws(Error)]
+    pub fn message(&self) -> SanitizedMessage {
+        let instructions = self
+            .instructions
+            .iter()
+            .map(|ix| {
+                Ok(Instruction::new_with_bytes(
+                    ix.program_id.0,
+                    &bs58::decode(&ix.data).into_vec()?,
+                    ix.accounts
+                        .iter()
+                        .map(|meta| AccountMeta {
+                            pubkey: meta.pubkey.0,
+                            is_signer: meta.signer,
+                            is_writable: meta.writable,
+                        })
+                        .collect(),
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let fee_payer = self.fee_payer.map(|address| address.0);
+        let message = Message::new(&instructions, fee_payer.as_ref());
+        SanitizedMessage::Legacy(LegacyMessage::new(message))
+    }
+
+    #[throws(Error)]
+    pub fn accounts(&self) -> Vec<(Pubkey, AccountSharedData)> {
+        self.accounts
+            .iter()
+            .map(|(address, account)| {
+                let account = Account {
+                    lamports: account.lamports,
+                    data: BASE64_STANDARD.decode(&account.data)?,
+                    owner: account.owner.0,
+                    executable: account.executable,
+                    rent_epoch: account.rent_epoch,
+                };
+                Ok((address.0, account.into()))
+            })
+            .collect::<Result<_, Error>>()?
+    }
+}
+
+impl Expect {
+    // The expectations that were not met. `result` is the outcome of MessageExecutor::process, or the
+    // error of a transaction that failed before execution.
+    pub fn check(&self, result: &Result<ExecutionRecord, Error>) -> Vec<String> {
+        let mut failures = vec![];
+
+        // Matched against both the message and the Debug form, e.g. InstructionError(0, Custom(1))
+        let error = match result {
+            Ok(record) => record.result().err().map(|e| {
+                let debug = format!("{:?}", record.status.as_ref().err());
+                (e.to_string(), debug)
+            }),
+            Err(e) => Some((
+                e.to_string(),
+                format!("{:?}", e.downcast_ref::<TransactionError>()),
+            )),
+        };
+        match (&self.error, error) {
+            (None, Some((error, _))) => failures.push(format!("expected success, got {}", error)),
+            (Some(expected), None) => {
+                failures.push(format!("expected error {}, got success", expected))
+            }
+            (Some(expected), Some((error, debug))) => {
+                let expected_lowercase = expected.to_lowercase();
+                if !error.to_lowercase().contains(&expected_lowercase)
+                    && !debug.to_lowercase().contains(&expected_lowercase)
+                {
+                    failures.push(format!("expected error {}, got {}", expected, error))
+                }
+            }
+            _ => {}
+        }
+
+        let record = match result {
+            Ok(record) => record,
+            Err(_) => return failures,
+        };
+
+        let logs = record.logs.concat();
+        for expected in &self.logs {
+            if !logs.iter().any(|log| log.contains(expected.as_str())) {
+                failures.push(format!("expected a log containing {}", expected));
+            }
+        }
+
+        if let Some(max_compute_units) = self.max_compute_units {
+            if record.cu > max_compute_units {
+                failures.push(format!(
+                    "expected at most {} compute units, consumed {}",
+                    max_compute_units, record.cu
+                ));
+            }
+        }
+
+        for (address, expected) in &self.accounts {
+            let account = match record.keys.iter().position(|key| *key == address.0) {
+                Some(index) => &record.datas[index],
+                None => {
+                    failures.push(format!(
+                        "{} is not an account of the transaction",
+                        address.0
+                    ));
+                    continue;
+                }
+            };
+            if let Some(lamports) = expected.lamports {
+                if account.lamports() != lamports {
+                    failures.push(format!(
+                        "expected {} lamports in {}, got {}",
+                        lamports,
+                        address.0,
+                        account.lamports()
+                    ));
+                }
+            }
+            if let Some(owner) = expected.owner {
+                if *account.owner() != owner.0 {
+                    failures.push(format!(
+                        "expected {} to be owned by {}, got {}",
+                        address.0,
+                        owner.0,
+                        account.owner()
+                    ));
+                }
+            }
+            if let Some(data) = &expected.data {
+                if BASE64_STANDARD.encode(account.data()) != *data {
+                    failures.push(format!("unexpected data in {}", address.0));
+                }
+            }
+        }
+
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Error;
+    use solana_accounts_db::rent_debits::RentDebits;
+    use solana_bpf_simulator::{system_account, ExecutionRecord};
+    use solana_program_runtime::loaded_programs::LoadedProgramsForTxBatch;
+    use solana_sdk::{
+        instruction::InstructionError, pubkey::Pubkey, system_program,
+        transaction::TransactionError,
+    };
+
+    use super::{Address, Expect, ExpectedAccount};
+
+    fn record(status: Result<(), TransactionError>, key: Pubkey) -> ExecutionRecord {
+        ExecutionRecord {
+            keys: vec![key],
+            pre_datas: vec![system_account(1_000_000)],
+            datas: vec![system_account(995_000)],
+            cu: 150,
+            fee: 5_000,
+            rent: 0,
+            rent_debits: RentDebits::default(),
+            tombstones: vec![],
+            programs_modified: LoadedProgramsForTxBatch::default(),
+            inner_instructions: vec![],
+            return_data: None,
+            logs: vec![vec!["Program log: hello".to_string()]],
+            status,
+        }
+    }
+
+    #[test]
+    fn status() {
+        let key = Pubkey::new_unique();
+        let success = Ok(record(Ok(()), key));
+        let failure = Ok(record(
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(1),
+            )),
+            key,
+        ));
+        let expect_error = |error: &str| Expect {
+            error: Some(error.to_string()),
+            ..Expect::default()
+        };
+
+        assert!(Expect::default().check(&success).is_empty());
+        assert_eq!(Expect::default().check(&failure).len(), 1);
+        assert!(expect_error("Custom(1)").check(&failure).is_empty());
+        assert!(expect_error("custom program error: 0x1")
+            .check(&failure)
+            .is_empty());
+        assert_eq!(expect_error("Custom(2)").check(&failure).len(), 1);
+        assert_eq!(expect_error("Custom(1)").check(&success).len(), 1);
+    }
+
+    #[test]
+    fn errors_before_execution() {
+        let failure: Result<ExecutionRecord, Error> =
+            Err(TransactionError::InsufficientFundsForFee.into());
+        let expect = Expect {
+            error: Some("insufficient funds".to_string()),
+            logs: vec!["hello".to_string()],
+            ..Expect::default()
+        };
+
+        // Nothing ran, so only the error is checked
+        assert!(expect.check(&failure).is_empty());
+        assert_eq!(Expect::default().check(&failure).len(), 1);
+    }
+
+    #[test]
+    fn logs_and_compute_units() {
+        let result = Ok(record(Ok(()), Pubkey::new_unique()));
+        let expect = |log: &str, max_compute_units| Expect {
+            logs: vec![log.to_string()],
+            max_compute_units: Some(max_compute_units),
+            ..Expect::default()
+        };
+
+        assert!(expect("hello", 150).check(&result).is_empty());
+        assert_eq!(expect("goodbye", 150).check(&result).len(), 1);
+        assert_eq!(expect("hello", 149).check(&result).len(), 1);
+        assert_eq!(expect("goodbye", 149).check(&result).len(), 2);
+    }
+
+    #[test]
+    fn accounts() {
+        let key = Pubkey::new_unique();
+        let result = Ok(record(Ok(()), key));
+        let expect = |key, lamports| Expect {
+            accounts: HashMap::from([(
+                Address(key),
+                ExpectedAccount {
+                    lamports: Some(lamports),
+                    owner: Some(Address(system_program::id())),
+                    data: Some(String::new()),
+                },
+            )]),
+            ..Expect::default()
+        };
+
+        assert!(expect(key, 995_000).check(&result).is_empty());
+        assert_eq!(expect(key, 1_000_000).check(&result).len(), 1);
+        assert_eq!(
+            expect(Pubkey::new_unique(), 995_000).check(&result).len(),
+            1
+        );
+    }
+}
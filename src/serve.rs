@@ -0,0 +1,751 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Read,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Error};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use clap::Parser;
+use fehler::{throw, throws};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use solana_account_decoder::UiAccountEncoding;
+use solana_bpf_simulator::{
+    native_program_account, upgradeable_program_accounts, AccountLoadError, AccountSource,
+    ExecutionRecord, MessageExecutor, RpcAccountSource, RpcSimulateTransactionResult, FEATURES,
+};
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcSendTransactionConfig, RpcSimulateTransactionConfig},
+    rpc_response::{Response, RpcApiVersion, RpcBlockhash, RpcResponseContext, RpcVersionInfo},
+};
+use solana_program_runtime::loaded_programs::DELAY_VISIBILITY_SLOT_OFFSET;
+use solana_runtime::builtins::BUILTINS;
+use solana_sdk::{
+    account::{AccountSharedData, ReadableAccount, WritableAccount},
+    clock::{Slot, UnixTimestamp, MAX_PROCESSING_AGE},
+    hash::Hash,
+    message::SanitizedMessage,
+    pubkey::Pubkey,
+    signature::Signature,
+    system_program,
+    transaction::{SanitizedTransaction, TransactionError, VersionedTransaction},
+};
+use solana_transaction_status::{TransactionBinaryEncoding, UiTransactionEncoding};
+use tiny_http::{Header, Method, Server};
+use tracing::{error, info};
+
+use crate::parse_key_value;
+
+#[derive(Debug, Clone, Parser)]
+pub struct Serve {
+    #[arg(long, default_value = "127.0.0.1:8899")]
+    bind: SocketAddr,
+
+    /// Start at this slot instead of the current slot of --solana-rpc
+    #[arg(long)]
+    slot: Option<Slot>,
+
+    /// Deploy a program from an ELF file, as <program id>=<path>
+    #[arg(long, value_parser = parse_key_value::<PathBuf>)]
+    program: Vec<(Pubkey, PathBuf)>,
+}
+
+impl Serve {
+    // Accounts that are not in the local store are fetched from `upstream` if given
+    #[throws(Error)]
+    pub fn run(&self, upstream: Option<&RpcClient>) {
+        let slot = match (self.slot, upstream) {
+            (Some(slot), _) => slot,
+            (None, Some(rpc)) => rpc.get_slot()?,
+            (None, None) => 0,
+        };
+
+        let mut node = LocalNode {
+            sbf: MessageExecutor::new(FEATURES)?,
+            store: HashMap::new(),
+            upstream: upstream.map(RpcAccountSource::new),
+            slot,
+            signatures: HashSet::new(),
+        };
+        node.set_slot(slot)?;
+
+        for (program_id, path) in &self.program {
+            let mut elf = vec![];
+            File::open(path)?.read_to_end(&mut elf)?;
+            let accounts = upgradeable_program_accounts(
+                program_id,
+                &elf,
+                None,
+                slot.saturating_sub(DELAY_VISIBILITY_SLOT_OFFSET),
+                &node.sbf.rent(),
+            )?;
+            node.store.extend(accounts);
+        }
+
+        let server = Server::http(self.bind).map_err(|e| anyhow!(e))?;
+        info!("Listening on http://{}", self.bind);
+
+        for mut request in server.incoming_requests() {
+            let mut body = String::new();
+            let response = match request.method() {
+                // CORS preflight of browser wallets
+                Method::Options => String::new(),
+                _ => match request.as_reader().read_to_string(&mut body) {
+                    Ok(_) => node.handle_body(&body).to_string(),
+                    Err(e) => error_response(Value::Null, RpcError::parse_error(e)).to_string(),
+                },
+            };
+
+            let response = tiny_http::Response::from_string(response)
+                .with_header(header("Content-Type", "application/json"))
+                .with_header(header("Access-Control-Allow-Origin", "*"))
+                .with_header(header("Access-Control-Allow-Headers", "*"));
+            if let Err(e) = request.respond(response) {
+                error!("Failed to respond: {}", e);
+            }
+        }
+    }
+}
+
+// A single node chain on top of MessageExecutor, advancing one slot per sent transaction
+struct LocalNode<'a> {
+    sbf: MessageExecutor,
+    store: HashMap<Pubkey, AccountSharedData>,
+    upstream: Option<RpcAccountSource<'a>>,
+    slot: Slot,
+    signatures: HashSet<Signature>, // of the committed transactions, like the status cache
+}
+
+impl<'a> LocalNode<'a> {
+    #[throws(Error)]
+    fn set_slot(&mut self, slot: Slot) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.sbf.set_synthetic_sysvars(slot, now as UnixTimestamp);
+        self.slot = slot;
+    }
+
+    fn handle_body(&mut self, body: &str) -> Value {
+        match serde_json::from_str(body) {
+            Ok(Value::Array(requests)) => {
+                Value::Array(requests.into_iter().map(|r| self.handle(r)).collect())
+            }
+            Ok(request) => self.handle(request),
+            Err(e) => error_response(Value::Null, RpcError::parse_error(e)),
+        }
+    }
+
+    fn handle(&mut self, request: Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or_default();
+        let method = request
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let params = request.get("params").cloned().unwrap_or_default();
+        info!("{}", method);
+
+        let result = match method {
+            "getAccountInfo" => self.get_account_info(&params),
+            "getMultipleAccounts" => self.get_multiple_accounts(&params),
+            "getLatestBlockhash" => self.get_latest_blockhash(),
+            "getVersion" => self.get_version(),
+            "requestAirdrop" => self.request_airdrop(&params),
+            "sendTransaction" => self.send_transaction(&params),
+            "simulateTransaction" => self.simulate_transaction(&params),
+            _ => Err(RpcError::method_not_found(method)),
+        };
+
+        match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "result": result, "id": id}),
+            Err(e) => error_response(id, e),
+        }
+    }
+
+    fn get_account_info(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let pubkey = parse_pubkey(&required::<String>(params, 0)?)?;
+        let config: RpcAccountInfoConfig = optional(params, 1)?.unwrap_or_default();
+        let encoding = config.encoding.unwrap_or(UiAccountEncoding::Binary);
+
+        let value = self.get_account(&pubkey)?.map(|account| {
+            solana_account_decoder::UiAccount::encode(
+                &pubkey,
+                &account,
+                encoding,
+                None,
+                config.data_slice,
+            )
+        });
+        self.response(value)
+    }
+
+    fn get_multiple_accounts(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let pubkeys = required::<Vec<String>>(params, 0)?;
+        let config: RpcAccountInfoConfig = optional(params, 1)?.unwrap_or_default();
+        let encoding = config.encoding.unwrap_or(UiAccountEncoding::Base64);
+
+        let mut value = vec![];
+        for pubkey in pubkeys {
+            let pubkey = parse_pubkey(&pubkey)?;
+            value.push(self.get_account(&pubkey)?.map(|account| {
+                solana_account_decoder::UiAccount::encode(
+                    &pubkey,
+                    &account,
+                    encoding,
+                    None,
+                    config.data_slice,
+                )
+            }));
+        }
+        self.response(value)
+    }
+
+    fn get_latest_blockhash(&mut self) -> Result<Value, RpcError> {
+        self.response(RpcBlockhash {
+            blockhash: self.latest_blockhash().to_string(),
+            last_valid_block_height: self.slot + MAX_PROCESSING_AGE as u64,
+        })
+    }
+
+    // RpcClient asks for the version before most requests
+    fn get_version(&mut self) -> Result<Value, RpcError> {
+        serde_json::to_value(RpcVersionInfo {
+            solana_core: RpcApiVersion::default().to_string(),
+            feature_set: None,
+        })
+        .map_err(RpcError::internal_error)
+    }
+
+    // Credit the account out of thin air, so that fee payers can be funded without an upstream node
+    fn request_airdrop(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let pubkey = parse_pubkey(&required::<String>(params, 0)?)?;
+        let lamports: u64 = required(params, 1)?;
+
+        let mut account = self
+            .get_account(&pubkey)?
+            .unwrap_or_else(|| AccountSharedData::new(0, 0, &system_program::id()));
+        account.set_lamports(account.lamports().saturating_add(lamports));
+        self.store.insert(pubkey, account);
+        Ok(json!(Signature::new_unique().to_string()))
+    }
+
+    fn send_transaction(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let config: RpcSendTransactionConfig = optional(params, 1)?.unwrap_or_default();
+        let tx = decode_transaction(&required::<String>(params, 0)?, config.encoding)?;
+        let signature = tx.signatures.first().copied().unwrap_or_default();
+        let tx = self.sanitize(tx, true)?;
+        if self.signatures.contains(&signature) {
+            return Err(RpcError::simulation_failed(
+                RpcSimulateTransactionResult::from_error(TransactionError::AlreadyProcessed),
+            ));
+        }
+
+        let record = match self.execute(&tx) {
+            Ok(record) => record,
+            Err(e) => match e.downcast::<TransactionError>() {
+                Ok(e) => {
+                    return Err(RpcError::simulation_failed(
+                        RpcSimulateTransactionResult::from_error(e),
+                    ))
+                }
+                Err(e) => return Err(RpcError::internal_error(e)),
+            },
+        };
+        if record.status.is_err() && !config.skip_preflight {
+            return Err(RpcError::simulation_failed(
+                record.to_rpc_simulate_result(None),
+            ));
+        }
+
+        self.commit(tx.message(), &record);
+        self.signatures.insert(signature);
+        self.set_slot(self.slot + 1)
+            .map_err(RpcError::internal_error)?;
+        Ok(json!(signature.to_string()))
+    }
+
+    fn simulate_transaction(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let config: RpcSimulateTransactionConfig = optional(params, 1)?.unwrap_or_default();
+        // Not part of RpcSimulateTransactionConfig in 1.17
+        let inner_instructions = params
+            .get(1)
+            .and_then(|config| config.get("innerInstructions"))
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+
+        let mut tx = decode_transaction(&required::<String>(params, 0)?, config.encoding)?;
+        if config.replace_recent_blockhash {
+            if config.sig_verify {
+                return Err(RpcError::invalid_params(
+                    "sigVerify may not be used with replaceRecentBlockhash",
+                ));
+            }
+            tx.message.set_recent_blockhash(self.latest_blockhash());
+        }
+        let tx = self.sanitize(tx, config.sig_verify)?;
+
+        let addresses = config
+            .accounts
+            .as_ref()
+            .map(|accounts| {
+                accounts
+                    .addresses
+                    .iter()
+                    .map(|address| parse_pubkey(address))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+
+        let mut value = match self.execute(&tx) {
+            Ok(record) => record.to_rpc_simulate_result(addresses.as_deref()),
+            Err(e) => match e.downcast::<TransactionError>() {
+                Ok(e) => RpcSimulateTransactionResult::from_error(e),
+                Err(e) => return Err(RpcError::internal_error(e)),
+            },
+        };
+        // Accounts outside of the transaction are returned as they are
+        if let Some((accounts, addresses)) = value.accounts.as_mut().zip(addresses) {
+            for (account, address) in accounts.iter_mut().zip(addresses) {
+                if account.is_none() {
+                    *account = self.get_account(&address)?.map(|account| {
+                        solana_account_decoder::UiAccount::encode(
+                            &address,
+                            &account,
+                            UiAccountEncoding::Base64,
+                            None,
+                            None,
+                        )
+                    });
+                }
+            }
+        }
+        if !inner_instructions {
+            value.inner_instructions = None;
+        }
+        self.response(value)
+    }
+
+    fn response<T: Serialize>(&self, value: T) -> Result<Value, RpcError> {
+        serde_json::to_value(Response {
+            context: RpcResponseContext::new(self.slot),
+            value,
+        })
+        .map_err(RpcError::internal_error)
+    }
+
+    #[allow(deprecated)]
+    fn latest_blockhash(&self) -> Hash {
+        self.sbf
+            .sysvar_cache()
+            .get_recent_blockhashes()
+            .ok()
+            .and_then(|blockhashes| blockhashes.first().map(|entry| entry.blockhash))
+            .unwrap_or_default()
+    }
+
+    // The local store, then the upstream node, then the synthetic sysvars and builtins. Accounts the
+    // upstream node fails to provide are an error rather than missing.
+    fn get_account(&mut self, pubkey: &Pubkey) -> Result<Option<AccountSharedData>, RpcError> {
+        get_account(&self.store, self.upstream.as_mut(), &self.sbf, pubkey)
+            .map_err(RpcError::internal_error)
+    }
+
+    fn sanitize(
+        &mut self,
+        tx: VersionedTransaction,
+        sig_verify: bool,
+    ) -> Result<SanitizedTransaction, RpcError> {
+        // The lookup table loader cannot fail, so the tables are fetched beforehand
+        let mut tables = HashMap::new();
+        for lookup in tx.message.address_table_lookups().unwrap_or_default() {
+            if let Some(account) = self.get_account(&lookup.account_key)? {
+                tables.insert(lookup.account_key, account);
+            }
+        }
+        let address_loader = self
+            .sbf
+            .lookup_table_loader(|key: &Pubkey| tables.get(key).cloned());
+
+        self.sbf
+            .sanitize_transaction_with_sig_verify(tx, address_loader, sig_verify)
+            .map_err(|e| match e {
+                TransactionError::SignatureFailure => RpcError {
+                    code: -32003,
+                    message: "Transaction signature verification failure".to_string(),
+                    data: None,
+                },
+                e => RpcError::invalid_params(format!("invalid transaction: {}", e)),
+            })
+    }
+
+    #[throws(Error)]
+    fn execute(&mut self, tx: &SanitizedTransaction) -> ExecutionRecord {
+        let message = tx.message();
+        let working_slot = self.sbf.working_slot(self.slot);
+
+        // The executor cannot look into the store while it is borrowed by the loader.
+        // Like Accounts::load_transaction_accounts, missing accounts are empty except for the fee payer.
+        let mut get_account =
+            |key| get_account(&self.store, self.upstream.as_mut(), &self.sbf, key);
+        if get_account(message.fee_payer())?.is_none() {
+            throw!(TransactionError::AccountNotFound);
+        }
+        let mut accounts = HashMap::new();
+        for key in message.account_keys().iter() {
+            accounts.insert(*key, get_account(key)?.unwrap_or_default());
+        }
+        let mut loader = self.sbf.loader(NodeSource {
+            accounts,
+            store: &self.store,
+            upstream: self.upstream.as_mut(),
+        });
+
+        let (loaded_transaction, loaded_programs) = loader.load(&working_slot, message)?;
+
+        self.sbf
+            .process(self.slot, message, loaded_transaction, &loaded_programs)?
+    }
+
+    // Bank::commit_transactions: failed transactions only pay the fee and the rent of the fee payer
+    fn commit(&mut self, message: &SanitizedMessage, record: &ExecutionRecord) {
+        if record.status.is_err() {
+            if let Some((payer, account)) = record.keys.first().zip(record.pre_datas.first()) {
+                let rent = record.rent_debits.get_account_rent_debit(payer);
+                let mut account = account.clone();
+                account.set_lamports(account.lamports().saturating_sub(rent + record.fee));
+                self.store.insert(*payer, account);
+            }
+            return;
+        }
+
+        for (i, (key, account)) in record.keys.iter().zip(&record.datas).enumerate() {
+            if message.is_writable(i) {
+                self.store.insert(*key, account.clone());
+            }
+        }
+        self.sbf.commit_programs(&record.programs_modified);
+    }
+}
+
+// The upstream source keeps the accounts it fetched, the store only holds local changes
+#[throws(AccountLoadError)]
+fn get_account(
+    store: &HashMap<Pubkey, AccountSharedData>,
+    upstream: Option<&mut RpcAccountSource>,
+    sbf: &MessageExecutor,
+    pubkey: &Pubkey,
+) -> Option<AccountSharedData> {
+    if let Some(account) = store.get(pubkey) {
+        return Some(account.clone());
+    }
+    if let Some(upstream) = upstream {
+        if let Some(account) = upstream.get_account(pubkey)? {
+            return Some(account);
+        }
+    }
+    if let Some(account) = sbf.sysvar_account(pubkey) {
+        return Some(account);
+    }
+    BUILTINS
+        .iter()
+        .find(|builtin| builtin.program_id == *pubkey)
+        .map(|builtin| native_program_account(builtin.name))
+}
+
+// The accounts of a transaction collected before loading, then the store and the upstream node for
+// the programs and loaders they refer to
+struct NodeSource<'s, 'a> {
+    accounts: HashMap<Pubkey, AccountSharedData>,
+    store: &'s HashMap<Pubkey, AccountSharedData>,
+    upstream: Option<&'s mut RpcAccountSource<'a>>,
+}
+
+impl<'s, 'a> AccountSource for NodeSource<'s, 'a> {
+    fn get_account(
+        &mut self,
+        pubkey: &Pubkey,
+    ) -> Result<Option<AccountSharedData>, AccountLoadError> {
+        if let Some(account) = self.accounts.get(pubkey).or_else(|| self.store.get(pubkey)) {
+            return Ok(Some(account.clone()));
+        }
+        match &mut self.upstream {
+            Some(upstream) => upstream.get_account(pubkey),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn parse_error(e: impl ToString) -> Self {
+        Self {
+            code: -32700,
+            message: e.to_string(),
+            data: None,
+        }
+    }
+
+    fn method_not_found(method: &str) -> Self {
+        Self {
+            code: -32601,
+            message: format!("Method not found: {}", method),
+            data: None,
+        }
+    }
+
+    fn invalid_params(e: impl ToString) -> Self {
+        Self {
+            code: -32602,
+            message: e.to_string(),
+            data: None,
+        }
+    }
+
+    fn internal_error(e: impl ToString) -> Self {
+        Self {
+            code: -32603,
+            message: e.to_string(),
+            data: None,
+        }
+    }
+
+    // The preflight failure of sendTransaction
+    fn simulation_failed(result: RpcSimulateTransactionResult) -> Self {
+        let message = match &result.err {
+            Some(e) => format!("Transaction simulation failed: {}", e),
+            None => "Transaction simulation failed".to_string(),
+        };
+        Self {
+            code: -32002,
+            message,
+            data: serde_json::to_value(result).ok(),
+        }
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({"jsonrpc": "2.0", "error": error, "id": id})
+}
+
+fn required<T: DeserializeOwned>(params: &Value, index: usize) -> Result<T, RpcError> {
+    optional(params, index)?
+        .ok_or_else(|| RpcError::invalid_params(format!("missing parameter {}", index)))
+}
+
+fn optional<T: DeserializeOwned>(params: &Value, index: usize) -> Result<Option<T>, RpcError> {
+    match params.get(index) {
+        None | Some(Value::Null) => Ok(None),
+        Some(param) => serde_json::from_value(param.clone())
+            .map(Some)
+            .map_err(RpcError::invalid_params),
+    }
+}
+
+fn parse_pubkey(pubkey: &str) -> Result<Pubkey, RpcError> {
+    pubkey
+        .parse()
+        .map_err(|_| RpcError::invalid_params(format!("Invalid param: {}", pubkey)))
+}
+
+fn decode_transaction(
+    data: &str,
+    encoding: Option<UiTransactionEncoding>,
+) -> Result<VersionedTransaction, RpcError> {
+    let bytes = match encoding
+        .unwrap_or(UiTransactionEncoding::Base58)
+        .into_binary_encoding()
+    {
+        Some(TransactionBinaryEncoding::Base58) => bs58::decode(data)
+            .into_vec()
+            .map_err(RpcError::invalid_params)?,
+        Some(TransactionBinaryEncoding::Base64) => BASE64_STANDARD
+            .decode(data)
+            .map_err(RpcError::invalid_params)?,
+        None => return Err(RpcError::invalid_params("unsupported encoding")),
+    };
+    bincode::deserialize(&bytes)
+        .map_err(|e| RpcError::invalid_params(format!("failed to deserialize transaction: {}", e)))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, collections::HashSet};
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use serde_json::{json, Value};
+    use solana_bpf_simulator::{system_account, MessageExecutor, FEATURES};
+    use solana_sdk::{
+        account::{AccountSharedData, ReadableAccount},
+        address_lookup_table::{
+            self,
+            state::{AddressLookupTable, LookupTableMeta},
+            AddressLookupTableAccount,
+        },
+        message::{v0, Message, VersionedMessage},
+        pubkey::Pubkey,
+        signature::{Keypair, Signature, Signer},
+        system_instruction,
+        transaction::VersionedTransaction,
+    };
+
+    use super::LocalNode;
+
+    const SLOT: u64 = 1000;
+
+    fn node(accounts: &[(Pubkey, AccountSharedData)]) -> LocalNode<'static> {
+        let mut node = LocalNode {
+            sbf: MessageExecutor::new(FEATURES).unwrap(),
+            store: accounts.iter().cloned().collect(),
+            upstream: None,
+            slot: SLOT,
+            signatures: HashSet::new(),
+        };
+        node.set_slot(SLOT).unwrap();
+        node
+    }
+
+    fn request(
+        node: &mut LocalNode,
+        method: &str,
+        tx: &VersionedTransaction,
+        config: Value,
+    ) -> Value {
+        let tx = BASE64_STANDARD.encode(bincode::serialize(tx).unwrap());
+        let mut config = config;
+        config["encoding"] = json!("base64");
+        node.handle(json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": [tx, config]}))
+    }
+
+    fn transfer(node: &LocalNode, payer: &Keypair, recipient: &Pubkey) -> VersionedTransaction {
+        let transfer = system_instruction::transfer(&payer.pubkey(), recipient, 1_000_000);
+        let mut message = Message::new(&[transfer], Some(&payer.pubkey()));
+        message.recent_blockhash = node.latest_blockhash();
+        VersionedTransaction::try_new(VersionedMessage::Legacy(message), &[payer]).unwrap()
+    }
+
+    fn lamports(account: &Value) -> Option<u64> {
+        account["lamports"].as_u64()
+    }
+
+    #[test]
+    fn simulate_returns_the_requested_accounts() {
+        let (payer, recipient, other) =
+            (Keypair::new(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut node = node(&[
+            (payer.pubkey(), system_account(1_000_000_000)),
+            (other, system_account(42)),
+        ]);
+        let tx = transfer(&node, &payer, &recipient);
+
+        let response = request(&mut node, "simulateTransaction", &tx, json!({}));
+        let value = &response["result"]["value"];
+        assert_eq!(value["err"], Value::Null);
+        assert_eq!(value["accounts"], Value::Null);
+
+        let missing = Pubkey::new_unique();
+        let addresses = [recipient, other, missing].map(|address| address.to_string());
+        let config = json!({"accounts": {"addresses": addresses, "encoding": "base64"}});
+        let response = request(&mut node, "simulateTransaction", &tx, config);
+        let accounts = response["result"]["value"]["accounts"].as_array().unwrap();
+        let accounts: Vec<_> = accounts.iter().map(lamports).collect();
+        assert_eq!(accounts, vec![Some(1_000_000), Some(42), None]);
+
+        // Simulations are not committed
+        assert!(!node.store.contains_key(&recipient));
+    }
+
+    #[test]
+    fn sig_verify_is_per_call() {
+        let (payer, recipient) = (Keypair::new(), Pubkey::new_unique());
+        let mut node = node(&[(payer.pubkey(), system_account(1_000_000_000))]);
+        let mut tx = transfer(&node, &payer, &recipient);
+        tx.signatures[0] = Signature::new_unique();
+
+        let response = request(
+            &mut node,
+            "simulateTransaction",
+            &tx,
+            json!({"sigVerify": true}),
+        );
+        assert_eq!(response["error"]["code"], -32003);
+        let response = request(&mut node, "simulateTransaction", &tx, json!({}));
+        assert_eq!(response["result"]["value"]["err"], Value::Null);
+        assert!(!node.sbf.sig_verify());
+
+        let response = request(&mut node, "sendTransaction", &tx, json!({}));
+        assert_eq!(response["error"]["code"], -32003);
+    }
+
+    #[test]
+    fn send_rejects_duplicate_signatures() {
+        let (payer, recipient) = (Keypair::new(), Pubkey::new_unique());
+        let mut node = node(&[(payer.pubkey(), system_account(1_000_000_000))]);
+        let tx = transfer(&node, &payer, &recipient);
+
+        let response = request(&mut node, "sendTransaction", &tx, json!({}));
+        assert_eq!(response["result"], tx.signatures[0].to_string());
+        assert_eq!(node.slot, SLOT + 1);
+
+        let response = request(&mut node, "sendTransaction", &tx, json!({}));
+        assert_eq!(response["error"]["code"], -32002);
+        assert_eq!(response["error"]["data"]["err"], "AlreadyProcessed");
+        assert_eq!(node.slot, SLOT + 1);
+        assert_eq!(node.store[&recipient].lamports(), 1_000_000);
+    }
+
+    #[test]
+    fn address_lookup_tables_are_resolved() {
+        let (payer, recipient, table) =
+            (Keypair::new(), Pubkey::new_unique(), Pubkey::new_unique());
+        let data = AddressLookupTable {
+            meta: LookupTableMeta::default(),
+            addresses: Cow::Owned(vec![recipient]),
+        }
+        .serialize_for_tests()
+        .unwrap();
+        let mut table_account = AccountSharedData::new(
+            1_000_000_000,
+            data.len(),
+            &address_lookup_table::program::id(),
+        );
+        table_account.set_data_from_slice(&data);
+        let mut node = node(&[
+            (payer.pubkey(), system_account(1_000_000_000)),
+            (table, table_account),
+        ]);
+
+        let transfer = system_instruction::transfer(&payer.pubkey(), &recipient, 1_000_000);
+        let message = v0::Message::try_compile(
+            &payer.pubkey(),
+            &[transfer],
+            &[AddressLookupTableAccount {
+                key: table,
+                addresses: vec![recipient],
+            }],
+            node.latest_blockhash(),
+        )
+        .unwrap();
+        assert_eq!(message.address_table_lookups.len(), 1);
+        let tx = VersionedTransaction::try_new(VersionedMessage::V0(message), &[&payer]).unwrap();
+
+        let config = json!({"sigVerify": true, "accounts": {"addresses": [recipient.to_string()]}});
+        let response = request(&mut node, "simulateTransaction", &tx, config);
+        let value = &response["result"]["value"];
+        assert_eq!(value["err"], Value::Null);
+        assert_eq!(lamports(&value["accounts"][0]), Some(1_000_000));
+    }
+}